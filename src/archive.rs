@@ -0,0 +1,190 @@
+use crate::error::ErrorKind;
+use crate::source;
+use failure::{Error, ResultExt};
+use flate2::read::GzDecoder;
+use git2::build::TreeUpdateBuilder;
+use git2::{FileMode, Oid, Repository, Signature, Time};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Prefix of the revision of an archive skeleton
+pub const REVISION_PREFIX: &str = "sha256:";
+
+const EXTENSIONS: &[&str] = &[".tar.gz", ".tgz", ".zip"];
+
+/// Returns the path if `url` is a local archive file or a `file://` URL of it.
+pub fn path(url: &str) -> Option<PathBuf> {
+    let path = Path::new(url.trim_start_matches("file://"));
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if EXTENSIONS.iter().any(|x| name.ends_with(x)) && path.is_file() {
+        Some(PathBuf::from(path))
+    } else {
+        None
+    }
+}
+
+/// Imports the archive into the cache repository, and opens it as a skeleton repository.
+///
+/// The content of each archive is kept in the cache as a commit referred by `refs/archives/<digest>`,
+/// so that the previously applied archive can be used as the base of update.
+pub fn open(path: &Path, dir: &Path) -> Result<Repository, Error> {
+    let cache = source::cache_dir()?.join("archives.git");
+    {
+        let repo = match Repository::open_bare(&cache) {
+            Ok(x) => x,
+            Err(_) => Repository::init_bare(&cache)?,
+        };
+        let id = import(&repo, path)
+            .context(ErrorKind::ArchiveLoad(path.to_string_lossy().to_string()))?;
+        repo.set_head_detached(id)?;
+    }
+
+    let (repo, _) = source::open_local(&cache, dir, false)?;
+    Ok(repo)
+}
+
+fn import(repo: &Repository, path: &Path) -> Result<Oid, Error> {
+    let data = fs::read(path)?;
+    let digest: String = Sha256::digest(&data)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+    let name = format!("refs/archives/{}", digest);
+    if let Ok(x) = repo.refname_to_id(&name) {
+        return Ok(x);
+    }
+
+    let name_lower = path.to_string_lossy().to_lowercase();
+    let entries = if name_lower.ends_with(".zip") {
+        read_zip(data)?
+    } else {
+        read_tar_gz(&data)?
+    };
+
+    let paths: Vec<_> = entries.iter().map(|(x, _, _)| x.as_str()).collect();
+    let root = common_root(&paths);
+
+    let empty = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+    let mut builder = TreeUpdateBuilder::new();
+    for (path, content, mode) in &entries {
+        builder.upsert(&path[root.len()..], repo.blob(content)?, *mode);
+    }
+    let tree = repo.find_tree(builder.create_updated(repo, &empty)?)?;
+
+    // The same archive is always imported as the same commit
+    let sig = Signature::new("git-skel", "git-skel", &Time::new(0, 0))?;
+    let message = format!("{}{}", REVISION_PREFIX, digest);
+    let id = repo.commit(None, &sig, &sig, &message, &tree, &[])?;
+    repo.reference(&name, id, true, "git-skel: archive")?;
+    Ok(id)
+}
+
+type Entry = (String, Vec<u8>, FileMode);
+
+fn read_tar_gz(data: &[u8]) -> Result<Vec<Entry>, Error> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let mut ret = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = match normalize(&entry.path()?) {
+            Some(x) => x,
+            None => continue,
+        };
+        let header = entry.header();
+        if header.entry_type().is_symlink() {
+            let target = match entry.link_name()? {
+                Some(x) => x.to_string_lossy().to_string(),
+                None => continue,
+            };
+            ret.push((path, target.into_bytes(), FileMode::Link));
+        } else if header.entry_type().is_file() {
+            let mode = if header.mode()? & 0o111 != 0 {
+                FileMode::BlobExecutable
+            } else {
+                FileMode::Blob
+            };
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            ret.push((path, content, mode));
+        }
+    }
+    Ok(ret)
+}
+
+fn read_zip(data: Vec<u8>) -> Result<Vec<Entry>, Error> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let mut ret = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = match file.enclosed_name().as_deref().and_then(normalize) {
+            Some(x) => x,
+            None => continue,
+        };
+        let mode = match file.unix_mode() {
+            Some(x) if x & 0o170_000 == 0o120_000 => FileMode::Link,
+            Some(x) if x & 0o111 != 0 => FileMode::BlobExecutable,
+            _ => FileMode::Blob,
+        };
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        ret.push((path, content, mode));
+    }
+    Ok(ret)
+}
+
+/// Returns the path separated by `/`, or `None` if the path is out of the archive.
+fn normalize(path: &Path) -> Option<String> {
+    let mut ret = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(x) => ret.push(x.to_string_lossy().to_string()),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    if ret.is_empty() {
+        None
+    } else {
+        Some(ret.join("/"))
+    }
+}
+
+/// Returns the top directory shared by all files ( like `skeleton-1.4/` ) to be stripped.
+fn common_root(paths: &[&str]) -> String {
+    let root = match paths.first().and_then(|x| x.split_once('/')) {
+        Some((x, _)) => format!("{}/", x),
+        None => return String::new(),
+    };
+    if paths.iter().all(|x| x.starts_with(&root)) {
+        root
+    } else {
+        String::new()
+    }
+}
+
+/// Returns the digest if the revision is of an archive skeleton.
+pub fn digest(revision: &str) -> Option<&str> {
+    revision.strip_prefix(REVISION_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_root() {
+        assert_eq!(
+            common_root(&["skel-1.4/LICENSE", "skel-1.4/src/main.rs"]),
+            "skel-1.4/"
+        );
+        assert_eq!(common_root(&["LICENSE", "src/main.rs"]), "");
+        assert_eq!(common_root(&["a/LICENSE", "b/main.rs"]), "");
+        assert_eq!(normalize(Path::new("./a/b")).as_deref(), Some("a/b"));
+        assert_eq!(normalize(Path::new("../a")), None);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::error::ErrorKind;
+use git2::{Cred, CredentialType, RemoteCallbacks};
+use std::cell::RefCell;
+use std::env;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Method {
+    SshAgent,
+    SshKey(PathBuf),
+    Token(&'static str),
+    CredentialHelper,
+    Username,
+    Default,
+}
+
+#[derive(Default)]
+struct State {
+    tried: Vec<Method>,
+    exhausted: bool,
+}
+
+/// Provides credentials to clone private skeleton repositories.
+///
+/// Each credential is tried once in the following order:
+///
+/// - ssh-agent
+/// - key files ( `GIT_SKEL_SSH_KEY`, `~/.ssh/id_ed25519`, `~/.ssh/id_ecdsa`, `~/.ssh/id_rsa` )
+/// - git credential helper ( `credential.helper` )
+/// - token environment variables ( `GIT_SKEL_TOKEN`, `GITHUB_TOKEN`, `GITLAB_TOKEN` )
+pub struct Auth {
+    config: Option<git2::Config>,
+    state: RefCell<State>,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Auth {
+            config: git2::Config::open_default().ok(),
+            state: RefCell::new(State::default()),
+        }
+    }
+
+    pub fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks
+            .credentials(move |url, username, allowed| self.credential(url, username, allowed));
+        callbacks
+    }
+
+    /// Returns the error describing the last tried credential if all credentials are rejected.
+    pub fn error(&self) -> Option<ErrorKind> {
+        let state = self.state.borrow();
+        if !state.exhausted {
+            return None;
+        }
+
+        let last = state
+            .tried
+            .iter()
+            .rev()
+            .find(|x| **x != Method::Username && **x != Method::Default);
+
+        let kind = match last {
+            Some(Method::SshAgent) => ErrorKind::AuthSshAgent,
+            Some(Method::SshKey(x)) => ErrorKind::AuthSshKey(x.to_string_lossy().to_string()),
+            Some(Method::Token(x)) => ErrorKind::AuthToken(String::from(*x)),
+            Some(Method::CredentialHelper) => ErrorKind::AuthCredentialHelper,
+            _ => ErrorKind::AuthNoCredential,
+        };
+        Some(kind)
+    }
+
+    fn credential(
+        &self,
+        url: &str,
+        username: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, git2::Error> {
+        let mut state = self.state.borrow_mut();
+
+        for method in candidates(url, allowed) {
+            if state.tried.contains(&method) {
+                continue;
+            }
+            state.tried.push(method.clone());
+
+            if let Ok(cred) = self.create(&method, url, username) {
+                return Ok(cred);
+            }
+        }
+
+        state.exhausted = true;
+        Err(git2::Error::from_str("no more credentials to try"))
+    }
+
+    fn create(
+        &self,
+        method: &Method,
+        url: &str,
+        username: Option<&str>,
+    ) -> Result<Cred, git2::Error> {
+        let ssh_user = username.unwrap_or("git");
+        match method {
+            Method::SshAgent => Cred::ssh_key_from_agent(ssh_user),
+            Method::SshKey(path) => {
+                let passphrase = env::var("GIT_SKEL_SSH_PASSPHRASE").ok();
+                Cred::ssh_key(ssh_user, None, path, passphrase.as_deref())
+            }
+            Method::Token(var) => {
+                let token = env::var(var).map_err(|_| git2::Error::from_str("token is not set"))?;
+                let user = username
+                    .map(String::from)
+                    .or_else(|| env::var("GIT_SKEL_USERNAME").ok())
+                    .unwrap_or_else(|| String::from(token_user(var)));
+                Cred::userpass_plaintext(&user, &token)
+            }
+            Method::CredentialHelper => match self.config {
+                Some(ref config) => Cred::credential_helper(config, url, username),
+                None => Err(git2::Error::from_str("git config is not found")),
+            },
+            Method::Username => Cred::username(ssh_user),
+            Method::Default => Cred::default(),
+        }
+    }
+}
+
+fn candidates(url: &str, allowed: CredentialType) -> Vec<Method> {
+    let mut ret = Vec::new();
+
+    if allowed.contains(CredentialType::USERNAME) {
+        ret.push(Method::Username);
+    }
+
+    if allowed.contains(CredentialType::SSH_KEY) {
+        ret.push(Method::SshAgent);
+        ret.extend(key_files().into_iter().map(Method::SshKey));
+    }
+
+    if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        ret.push(Method::CredentialHelper);
+        let vars = [
+            ("GIT_SKEL_TOKEN", ""),
+            ("GITHUB_TOKEN", "github"),
+            ("GITLAB_TOKEN", "gitlab"),
+        ];
+        for (var, host) in &vars {
+            if url.contains(host) && env::var_os(var).is_some() {
+                ret.push(Method::Token(var));
+            }
+        }
+    }
+
+    if allowed.contains(CredentialType::DEFAULT) {
+        ret.push(Method::Default);
+    }
+
+    ret
+}
+
+fn key_files() -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+
+    if let Some(path) = env::var_os("GIT_SKEL_SSH_KEY") {
+        ret.push(PathBuf::from(path));
+    }
+
+    if let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+        let ssh = PathBuf::from(home).join(".ssh");
+        for name in &["id_ed25519", "id_ecdsa", "id_rsa"] {
+            let path = ssh.join(name);
+            if path.exists() {
+                ret.push(path);
+            }
+        }
+    }
+
+    ret
+}
+
+fn token_user(var: &str) -> &'static str {
+    match var {
+        "GITLAB_TOKEN" => "oauth2",
+        _ => "x-access-token",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        // Only this test changes the environment variables of credentials
+        env::set_var("GIT_SKEL_SSH_KEY", "/tmp/git-skel-test-key");
+        env::set_var("GIT_SKEL_TOKEN", "token");
+        env::remove_var("GITHUB_TOKEN");
+        env::remove_var("GITLAB_TOKEN");
+
+        let mut expected = vec![Method::SshAgent];
+        expected.extend(key_files().into_iter().map(Method::SshKey));
+        expected.push(Method::CredentialHelper);
+        expected.push(Method::Token("GIT_SKEL_TOKEN"));
+        let allowed = CredentialType::SSH_KEY | CredentialType::USER_PASS_PLAINTEXT;
+        let methods = candidates("https://example.com/skel.git", allowed);
+        assert_eq!(methods, expected);
+        assert_eq!(
+            methods[1],
+            Method::SshKey(PathBuf::from("/tmp/git-skel-test-key"))
+        );
+
+        // SSH asks the user name before the key
+        let methods = candidates("git@example.com:skel.git", CredentialType::USERNAME);
+        assert_eq!(methods, vec![Method::Username]);
+
+        env::remove_var("GIT_SKEL_SSH_KEY");
+        env::remove_var("GIT_SKEL_TOKEN");
+    }
+
+    #[test]
+    fn test_error() {
+        let auth = Auth::new();
+        let set = |tried: Vec<Method>, exhausted: bool| {
+            *auth.state.borrow_mut() = State { tried, exhausted };
+        };
+
+        // No error until all credentials are tried
+        set(vec![Method::SshAgent], false);
+        assert!(auth.error().is_none());
+
+        set(vec![Method::Username, Method::SshAgent], true);
+        assert!(matches!(auth.error(), Some(ErrorKind::AuthSshAgent)));
+
+        let key = PathBuf::from("/home/user/.ssh/id_ed25519");
+        set(vec![Method::SshAgent, Method::SshKey(key)], true);
+        assert!(matches!(auth.error(), Some(ErrorKind::AuthSshKey(_))));
+
+        set(vec![Method::CredentialHelper], true);
+        assert!(matches!(
+            auth.error(),
+            Some(ErrorKind::AuthCredentialHelper)
+        ));
+
+        // The last tried credential is reported
+        set(
+            vec![Method::CredentialHelper, Method::Token("GIT_SKEL_TOKEN")],
+            true,
+        );
+        assert!(matches!(auth.error(), Some(ErrorKind::AuthToken(_))));
+
+        set(vec![Method::Username, Method::Default], true);
+        assert!(matches!(auth.error(), Some(ErrorKind::AuthNoCredential)));
+    }
+}
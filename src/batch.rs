@@ -0,0 +1,146 @@
+use crate::error::ErrorKind;
+use failure::{Error, ResultExt};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Manifest of `git skel batch`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Batch {
+    #[serde(rename = "repository")]
+    pub repositories: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Path to the repository relative to the manifest
+    pub path: PathBuf,
+    /// URL to clone the repository into `path` if it doesn't exist
+    pub clone: Option<String>,
+}
+
+impl Batch {
+    pub fn load(path: &Path) -> Result<Batch, Error> {
+        fn load_file(path: &Path) -> Result<Batch, Error> {
+            let s = fs::read_to_string(path)?;
+            let batch = toml::from_str(&s)?;
+            Ok(batch)
+        }
+
+        let mut batch =
+            load_file(path).context(ErrorKind::BatchLoad(path.to_string_lossy().to_string()))?;
+
+        if let Some(root) = path.parent() {
+            for entry in &mut batch.repositories {
+                entry.path = root.join(&entry.path);
+            }
+        }
+
+        Ok(batch)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    UpToDate,
+    Updated,
+    Conflicts,
+    Error(String),
+}
+
+impl Outcome {
+    pub fn from_error(err: &Error) -> Outcome {
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::AbortByModified) => Outcome::Conflicts,
+            _ => {
+                let causes: Vec<_> = err.iter_chain().map(|x| format!("{}", x)).collect();
+                Outcome::Error(causes.join(": "))
+            }
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Outcome::UpToDate | Outcome::Updated)
+    }
+}
+
+/// Fails with the number of repositories which are not updated.
+pub fn finish(outcomes: &[Outcome]) -> Result<(), Error> {
+    let failed = outcomes.iter().filter(|x| !x.is_ok()).count();
+    if failed != 0 {
+        return Err(ErrorKind::AbortByBatch(failed).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("batch.toml");
+        fs::write(
+            &path,
+            "[[repository]]\npath = \"a\"\n\n[[repository]]\npath = \"b\"\nclone = \"https://example.com/b.git\"\n",
+        )
+        .unwrap();
+
+        let batch = Batch::load(&path).unwrap();
+        assert_eq!(batch.repositories.len(), 2);
+        assert_eq!(batch.repositories[0].path, dir.path().join("a"));
+        assert_eq!(batch.repositories[0].clone, None);
+        assert_eq!(
+            batch.repositories[1].clone.as_deref(),
+            Some("https://example.com/b.git")
+        );
+    }
+
+    #[test]
+    fn test_outcome() {
+        let err = Error::from(ErrorKind::AbortByModified);
+        assert_eq!(Outcome::from_error(&err), Outcome::Conflicts);
+
+        let err = Error::from(ErrorKind::RevisionNotFound(String::from("abc")));
+        assert_eq!(
+            Outcome::from_error(&err),
+            Outcome::Error(format!(
+                "{}",
+                ErrorKind::RevisionNotFound(String::from("abc"))
+            ))
+        );
+
+        // Causes are joined, so the error of a repository fits in a line of the summary
+        let err = Error::from(
+            failure::err_msg("unsupported URL protocol")
+                .context(ErrorKind::RepoClone(String::from("../skel"))),
+        );
+        match Outcome::from_error(&err) {
+            Outcome::Error(x) => assert!(x.ends_with(": unsupported URL protocol")),
+            x => panic!("unexpected outcome: {:?}", x),
+        }
+
+        assert!(Outcome::UpToDate.is_ok());
+        assert!(Outcome::Updated.is_ok());
+        assert!(!Outcome::Conflicts.is_ok());
+        assert!(!Outcome::Error(String::new()).is_ok());
+    }
+
+    #[test]
+    fn test_finish() {
+        assert!(finish(&[]).is_ok());
+        assert!(finish(&[Outcome::UpToDate, Outcome::Updated]).is_ok());
+
+        let err = finish(&[
+            Outcome::Updated,
+            Outcome::Conflicts,
+            Outcome::Error(String::from("x")),
+        ])
+        .unwrap_err();
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::AbortByBatch(x)) => assert_eq!(*x, 2),
+            x => panic!("unexpected error: {:?}", x),
+        }
+    }
+}
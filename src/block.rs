@@ -0,0 +1,89 @@
+pub const BEGIN_MARKER: &str = ">>> git-skel";
+pub const END_MARKER: &str = "<<< git-skel";
+
+/// Returns the managed block of skeleton file including marker lines.
+///
+/// If the skeleton file has marker lines, the region between them is the block.
+/// Otherwise the whole content is surrounded by the default markers ( `# >>> git-skel` and `# <<< git-skel` ).
+pub fn extract(src: &str) -> String {
+    if let Some((begin, end)) = find(src) {
+        String::from(&src[begin..end])
+    } else {
+        let mut ret = format!("# {}\n", BEGIN_MARKER);
+        ret.push_str(src);
+        if !src.is_empty() && !src.ends_with('\n') {
+            ret.push('\n');
+        }
+        ret.push_str(&format!("# {}\n", END_MARKER));
+        ret
+    }
+}
+
+/// Returns the managed block of target file including marker lines.
+pub fn get(tgt: &str) -> Option<&str> {
+    find(tgt).map(|(begin, end)| &tgt[begin..end])
+}
+
+/// Replaces the managed block of target file by `block`, or appends `block` if target file has no block.
+pub fn replace(tgt: &str, block: &str) -> String {
+    if let Some((begin, end)) = find(tgt) {
+        format!("{}{}{}", &tgt[..begin], block, &tgt[end..])
+    } else if tgt.is_empty() || tgt.ends_with('\n') {
+        format!("{}{}", tgt, block)
+    } else {
+        format!("{}\n{}", tgt, block)
+    }
+}
+
+/// Removes the managed block from target file.
+pub fn remove(tgt: &str) -> String {
+    if let Some((begin, end)) = find(tgt) {
+        format!("{}{}", &tgt[..begin], &tgt[end..])
+    } else {
+        String::from(tgt)
+    }
+}
+
+/// Returns the byte range from the beginning of the begin marker line to the end of the end marker line.
+fn find(text: &str) -> Option<(usize, usize)> {
+    let mut begin = None;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        if begin.is_none() && line.contains(BEGIN_MARKER) {
+            begin = Some(pos);
+        } else if begin.is_some() && line.contains(END_MARKER) {
+            return begin.map(|x| (x, pos + line.len()));
+        }
+        pos += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block() {
+        let block = extract("target/\n");
+        assert_eq!(block, "# >>> git-skel\ntarget/\n# <<< git-skel\n");
+
+        let block_md = extract("title\n<!-- >>> git-skel -->\nbadge\n<!-- <<< git-skel -->\n");
+        assert_eq!(
+            block_md,
+            "<!-- >>> git-skel -->\nbadge\n<!-- <<< git-skel -->\n"
+        );
+
+        let tgt = "*.o";
+        let tgt = replace(tgt, &block);
+        assert_eq!(tgt, "*.o\n# >>> git-skel\ntarget/\n# <<< git-skel\n");
+        assert_eq!(get(&tgt), Some(block.as_str()));
+
+        let tgt = format!("{}*.a\n", tgt);
+        let tgt = replace(&tgt, "# >>> git-skel\nbuild/\n# <<< git-skel\n");
+        assert_eq!(tgt, "*.o\n# >>> git-skel\nbuild/\n# <<< git-skel\n*.a\n");
+
+        assert_eq!(remove(&tgt), "*.o\n*.a\n");
+        assert_eq!(get("*.o\n"), None);
+    }
+}
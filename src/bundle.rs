@@ -0,0 +1,175 @@
+use crate::error::ErrorKind;
+use crate::source;
+use failure::{bail, Error, ResultExt};
+use git2::{Oid, Repository};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Returns the path if `url` is a local git bundle file or a `file://` URL of it.
+pub fn path(url: &str) -> Option<PathBuf> {
+    let path = Path::new(url.trim_start_matches("file://"));
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".bundle") && path.is_file() {
+        Some(PathBuf::from(path))
+    } else {
+        None
+    }
+}
+
+/// Unpacks the bundle into the cache mirror, and opens it as a skeleton repository.
+///
+/// All bundles share the objects of the mirror, so that incremental bundles can be unpacked,
+/// and previously applied revisions can be used as the base of update.
+pub fn open(path: &Path, dir: &Path) -> Result<Repository, Error> {
+    let cache = source::cache_dir()?.join("bundles.git");
+    let mirror = match Repository::open_bare(&cache) {
+        Ok(x) => x,
+        Err(_) => Repository::init_bare(&cache)?,
+    };
+
+    let data = fs::read(path).context(ErrorKind::BundleLoad(path.to_string_lossy().to_string()))?;
+    let bundle = unpack(&mirror, &data)
+        .context(ErrorKind::BundleLoad(path.to_string_lossy().to_string()))?;
+
+    source::open_shared(
+        &mirror.path().join("objects"),
+        dir,
+        &bundle.refs,
+        bundle.head,
+    )
+}
+
+struct Bundle {
+    refs: Vec<(String, Oid)>,
+    head: Oid,
+}
+
+fn unpack(mirror: &Repository, data: &[u8]) -> Result<Bundle, Error> {
+    let header = parse(data)?;
+
+    for prerequisite in &header.prerequisites {
+        if mirror.find_commit(*prerequisite).is_err() {
+            bail!(ErrorKind::BundlePrerequisiteNotFound(
+                prerequisite.to_string()
+            ));
+        }
+    }
+
+    let odb = mirror.odb()?;
+    let mut writer = odb.packwriter()?;
+    writer.write_all(&data[header.pack..])?;
+    writer.commit()?;
+
+    // HEAD of the bundle, or the default branch is checked out as the same as clone
+    let head = header
+        .refs
+        .iter()
+        .find(|(name, _)| name == "HEAD")
+        .or_else(|| {
+            ["refs/heads/master", "refs/heads/main"]
+                .iter()
+                .find_map(|x| header.refs.iter().find(|(name, _)| name == x))
+        })
+        .or_else(|| {
+            header
+                .refs
+                .iter()
+                .find(|(name, _)| name.starts_with("refs/heads/"))
+        });
+    let head = match head {
+        Some((_, id)) => *id,
+        None => bail!(ErrorKind::BundleInvalid(String::from("no branch"))),
+    };
+
+    Ok(Bundle {
+        refs: header.refs,
+        head,
+    })
+}
+
+struct Header {
+    prerequisites: Vec<Oid>,
+    refs: Vec<(String, Oid)>,
+    /// Offset of the packfile
+    pack: usize,
+}
+
+/// Parses the header of git bundle v2 or v3.
+fn parse(data: &[u8]) -> Result<Header, Error> {
+    let mut header = Header {
+        prerequisites: Vec::new(),
+        refs: Vec::new(),
+        pack: 0,
+    };
+
+    let mut lines = data.split(|x| *x == b'\n');
+    let mut offset = 0;
+    let mut next = || -> Result<&str, Error> {
+        let line = match lines.next() {
+            Some(x) => x,
+            None => bail!(ErrorKind::BundleInvalid(String::from("truncated header"))),
+        };
+        offset += line.len() + 1;
+        Ok(std::str::from_utf8(line)?)
+    };
+
+    match next()? {
+        "# v2 git bundle" | "# v3 git bundle" => (),
+        x => bail!(ErrorKind::BundleInvalid(format!(
+            "unsupported format: {}",
+            x
+        ))),
+    }
+
+    loop {
+        let line = next()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(capability) = line.strip_prefix('@') {
+            if capability.starts_with("object-format=") && capability != "object-format=sha1" {
+                bail!(ErrorKind::BundleInvalid(format!(
+                    "unsupported capability: {}",
+                    capability
+                )));
+            }
+        } else if let Some(prerequisite) = line.strip_prefix('-') {
+            let id = prerequisite.split(' ').next().unwrap_or_default();
+            header.prerequisites.push(Oid::from_str(id)?);
+        } else {
+            match line.split_once(' ') {
+                Some((id, name)) => header.refs.push((String::from(name), Oid::from_str(id)?)),
+                None => bail!(ErrorKind::BundleInvalid(format!(
+                    "invalid header: {}",
+                    line
+                ))),
+            }
+        }
+    }
+
+    header.pack = offset;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let id0 = "f5ca092fecc42c260131f5c42a64f67d4cf58561";
+        let id1 = "c6b0b0c429eed41582fc0991f5e71fec9b68b4cc";
+        let data = format!(
+            "# v3 git bundle\n@object-format=sha1\n-{} base\n{} refs/heads/master\n{} HEAD\n\nPACK",
+            id0, id1, id1
+        );
+        let header = parse(data.as_bytes()).unwrap();
+        assert_eq!(header.prerequisites, vec![Oid::from_str(id0).unwrap()]);
+        assert_eq!(header.refs.len(), 2);
+        assert_eq!(header.refs[0].0, "refs/heads/master");
+        assert_eq!(&data.as_bytes()[header.pack..], b"PACK");
+
+        assert!(parse(b"# v4 git bundle\n\n").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use failure::{bail, Error};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Selectable set of files declared by `[[component]]` of skeleton manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Component {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub paths: Vec<String>,
+    /// Whether the component is enabled at init
+    #[serde(default = "default_true")]
+    pub default: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub struct Components {
+    components: Vec<(String, GlobSet)>,
+}
+
+impl Components {
+    pub fn new(components: &[Component]) -> Result<Components, Error> {
+        let mut ret = Vec::new();
+        for component in components {
+            if ret.iter().any(|(name, _)| name == &component.name) {
+                bail!("component is declared twice: {}", component.name);
+            }
+            let mut builder = GlobSetBuilder::new();
+            for path in &component.paths {
+                builder.add(GlobBuilder::new(path).literal_separator(true).build()?);
+            }
+            ret.push((component.name.clone(), builder.build()?));
+        }
+        Ok(Components { components: ret })
+    }
+
+    /// Returns true if the file doesn't belong to any component, or belongs to an enabled component.
+    pub fn is_included(&self, path: &Path, enabled: &BTreeMap<String, bool>) -> bool {
+        let mut matched = false;
+        for (name, globs) in &self.components {
+            if globs.is_match(path) {
+                if enabled.get(name) == Some(&true) {
+                    return true;
+                }
+                matched = true;
+            }
+        }
+        !matched
+    }
+
+    /// Returns true if the file is matched by the paths of the component.
+    pub fn is_match(&self, name: &str, path: &Path) -> bool {
+        self.components
+            .iter()
+            .any(|(x, globs)| x == name && globs.is_match(path))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.components.iter().any(|(x, _)| x == name)
+    }
+}
+
+/// Sets the default to the components which are not recorded yet.
+pub fn resolve(components: &[Component], enabled: &mut BTreeMap<String, bool>) {
+    for component in components {
+        enabled
+            .entry(component.name.clone())
+            .or_insert(component.default);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_components() {
+        let components: Vec<Component> = toml::from_str::<toml::Table>(
+            r#"
+            component = [
+                { name = "ci", paths = [".github/**"] },
+                { name = "docker", paths = ["docker/**", "Dockerfile"], default = false },
+                { name = "container", paths = ["Dockerfile"], default = false },
+            ]
+            "#,
+        )
+        .unwrap()["component"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let mut enabled = BTreeMap::new();
+        resolve(&components, &mut enabled);
+        assert_eq!(enabled.get("ci"), Some(&true));
+        assert_eq!(enabled.get("docker"), Some(&false));
+
+        let components = Components::new(&components).unwrap();
+        assert!(components.contains("docker"));
+        assert!(components.is_match("docker", Path::new("docker/a")));
+        assert!(!components.is_match("ci", Path::new("docker/a")));
+        assert!(components.is_included(Path::new("src/main.rs"), &enabled));
+        assert!(components.is_included(Path::new(".github/ci.yml"), &enabled));
+        assert!(!components.is_included(Path::new("docker/a"), &enabled));
+        assert!(!components.is_included(Path::new("Dockerfile"), &enabled));
+
+        enabled.insert(String::from("container"), true);
+        assert!(components.is_included(Path::new("Dockerfile"), &enabled));
+        assert!(!components.is_included(Path::new("docker/a"), &enabled));
+    }
+}
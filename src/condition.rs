@@ -0,0 +1,313 @@
+use failure::{bail, format_err, Error};
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::Path;
+use toml::{Table, Value};
+
+/// Files included only when the condition is true, declared by `conditions` of skeleton manifest
+///
+/// Each condition is written as `<glob> when <expression>` ( e.g. `docker/** when use_docker` ).
+pub struct Conditions {
+    conditions: Vec<(GlobMatcher, Expr)>,
+}
+
+impl Conditions {
+    pub fn new(conditions: &[String]) -> Result<Conditions, Error> {
+        let mut ret = Vec::new();
+        for condition in conditions {
+            let (glob, expr) = parse(condition)?;
+            ret.push((glob, expr));
+        }
+        Ok(Conditions { conditions: ret })
+    }
+
+    /// Returns true if all conditions matched with `path` are true.
+    pub fn is_included(&self, path: &Path, vars: &Table) -> bool {
+        self.conditions
+            .iter()
+            .filter(|(glob, _)| glob.is_match(path))
+            .all(|(_, expr)| truthy(&expr.eval(vars)))
+    }
+
+    /// Returns the variable names used by the conditions.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut ret = Vec::new();
+        for (_, expr) in &self.conditions {
+            expr.variables(&mut ret);
+        }
+        ret
+    }
+}
+
+fn parse(condition: &str) -> Result<(GlobMatcher, Expr), Error> {
+    let (glob, expr) = match condition.find(" when ") {
+        Some(i) => (condition[..i].trim(), &condition[i + 6..]),
+        None => bail!(
+            "condition must be `<glob> when <expression>`: {}",
+            condition
+        ),
+    };
+    let glob = GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+
+    let tokens = tokenize(expr).map_err(|x| format_err!("{}: {}", condition, x))?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser
+        .parse_or()
+        .map_err(|x| format_err!("{}: {}", condition, x))?;
+    if parser.pos != parser.tokens.len() {
+        bail!(
+            "{}: unexpected token: {:?}",
+            condition,
+            parser.tokens[parser.pos]
+        );
+    }
+    Ok((glob, expr))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+    LParen,
+    RParen,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            ' ' | '\t' => i += 1,
+            '(' => {
+                ret.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                ret.push(Token::RParen);
+                i += 1;
+            }
+            '!' if next == Some('=') => {
+                ret.push(Token::Ne);
+                i += 2;
+            }
+            '!' => {
+                ret.push(Token::Not);
+                i += 1;
+            }
+            '=' if next == Some('=') => {
+                ret.push(Token::Eq);
+                i += 2;
+            }
+            '&' if next == Some('&') => {
+                ret.push(Token::And);
+                i += 2;
+            }
+            '|' if next == Some('|') => {
+                ret.push(Token::Or);
+                i += 2;
+            }
+            '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|x| *x == '"')
+                    .ok_or_else(|| format_err!("unterminated string"))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+                ret.push(Token::Literal(Value::String(text)));
+                i += end + 2;
+            }
+            _ if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|x| x.is_ascii_alphanumeric() || **x == '_' || **x == '-')
+                    .count();
+                let word: String = chars[i..i + len].iter().collect();
+                let token = match word.as_str() {
+                    "true" => Token::Literal(Value::Boolean(true)),
+                    "false" => Token::Literal(Value::Boolean(false)),
+                    _ => match word.parse::<i64>() {
+                        Ok(x) => Token::Literal(Value::Integer(x)),
+                        Err(_) => Token::Ident(word),
+                    },
+                };
+                ret.push(token);
+                i += len;
+            }
+            _ => bail!("unexpected character: {}", c),
+        }
+    }
+    Ok(ret)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Var(String),
+    Literal(Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, vars: &Table) -> Option<Value> {
+        match self {
+            Expr::Var(x) => vars.get(x).cloned(),
+            Expr::Literal(x) => Some(x.clone()),
+            Expr::Not(x) => Some(Value::Boolean(!truthy(&x.eval(vars)))),
+            Expr::And(x, y) => Some(Value::Boolean(
+                truthy(&x.eval(vars)) && truthy(&y.eval(vars)),
+            )),
+            Expr::Or(x, y) => Some(Value::Boolean(
+                truthy(&x.eval(vars)) || truthy(&y.eval(vars)),
+            )),
+            Expr::Eq(x, y) => Some(Value::Boolean(x.eval(vars) == y.eval(vars))),
+            Expr::Ne(x, y) => Some(Value::Boolean(x.eval(vars) != y.eval(vars))),
+        }
+    }
+
+    fn variables<'a>(&'a self, ret: &mut Vec<&'a str>) {
+        match self {
+            Expr::Var(x) => ret.push(x),
+            Expr::Literal(_) => (),
+            Expr::Not(x) => x.variables(ret),
+            Expr::And(x, y) | Expr::Or(x, y) | Expr::Eq(x, y) | Expr::Ne(x, y) => {
+                x.variables(ret);
+                y.variables(ret);
+            }
+        }
+    }
+}
+
+/// Unset variables, `false`, `""` and `0` are false.
+fn truthy(value: &Option<Value>) -> bool {
+    match value {
+        None => false,
+        Some(Value::Boolean(x)) => *x,
+        Some(Value::String(x)) => !x.is_empty(),
+        Some(Value::Integer(x)) => *x != 0,
+        Some(_) => true,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Error> {
+        let mut ret = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            ret = Expr::Or(Box::new(ret), Box::new(self.parse_and()?));
+        }
+        Ok(ret)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Error> {
+        let mut ret = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            ret = Expr::And(Box::new(ret), Box::new(self.parse_not()?));
+        }
+        Ok(ret)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, Error> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr, Error> {
+        let lhs = self.parse_primary()?;
+        match self.peek() {
+            Some(Token::Eq) => {
+                self.pos += 1;
+                Ok(Expr::Eq(Box::new(lhs), Box::new(self.parse_primary()?)))
+            }
+            Some(Token::Ne) => {
+                self.pos += 1;
+                Ok(Expr::Ne(Box::new(lhs), Box::new(self.parse_primary()?)))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Ident(x)) => Ok(Expr::Var(x)),
+            Some(Token::Literal(x)) => Ok(Expr::Literal(x)),
+            Some(Token::LParen) => {
+                let ret = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    bail!("missing `)`");
+                }
+                self.pos += 1;
+                Ok(ret)
+            }
+            Some(x) => bail!("unexpected token: {:?}", x),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        let conditions = Conditions::new(&[
+            String::from("docker/** when use_docker"),
+            String::from("helm/** when use_docker && (env == \"prod\" || replicas != 1)"),
+            String::from("benches/** when !skip_bench"),
+        ])
+        .unwrap();
+        assert_eq!(
+            conditions.variables(),
+            vec!["use_docker", "use_docker", "env", "replicas", "skip_bench"]
+        );
+
+        let mut vars = Table::new();
+        vars.insert(String::from("use_docker"), Value::from(true));
+        vars.insert(String::from("env"), Value::from("dev"));
+        vars.insert(String::from("replicas"), Value::from(1));
+
+        assert!(conditions.is_included(Path::new("src/main.rs"), &vars));
+        assert!(conditions.is_included(Path::new("docker/Dockerfile"), &vars));
+        assert!(!conditions.is_included(Path::new("helm/values.yaml"), &vars));
+        assert!(conditions.is_included(Path::new("benches/a.rs"), &vars));
+
+        vars.insert(String::from("env"), Value::from("prod"));
+        vars.insert(String::from("use_docker"), Value::from(false));
+        vars.insert(String::from("skip_bench"), Value::from(true));
+        assert!(!conditions.is_included(Path::new("docker/Dockerfile"), &vars));
+        assert!(!conditions.is_included(Path::new("helm/values.yaml"), &vars));
+        assert!(!conditions.is_included(Path::new("benches/a.rs"), &vars));
+
+        assert!(Conditions::new(&[String::from("docker/**")]).is_err());
+        assert!(Conditions::new(&[String::from("a when (b")]).is_err());
+        assert!(Conditions::new(&[String::from("a when b c")]).is_err());
+        assert!(Conditions::new(&[String::from("a when b == \"c")]).is_err());
+    }
+}
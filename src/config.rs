@@ -1,56 +1,260 @@
 use crate::error::ErrorKind;
+use crate::strategy::Rule;
 use failure::{bail, Error, ResultExt};
-use git2::{Commit, Repository};
+use git2::{ConfigLevel, Repository};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use toml::Table;
+use toml_edit::{DocumentMut, Item};
+
+/// Current version of the layout of `.gitskel.toml`
+pub const CONFIG_VERSION: i64 = 1;
+
+type Migration = fn(&mut Table) -> Result<(), Error>;
+
+/// Migrations of `.gitskel.toml` from each version to the next version
+const MIGRATIONS: &[Migration] = &[migrate_v0];
+
+/// Origin of values written in `.gitskel.toml`
+pub const ORIGIN_CONFIG: &str = ".gitskel.toml";
+
+/// Origin of values overridden by the local override file, which is not committed
+pub const ORIGIN_LOCAL: &str = ".gitskel.local.toml";
+
+/// Origin of values overridden by `[skel]` section of `.git/config`
+pub const ORIGIN_GIT: &str = ".git/config";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
+    pub version: i64,
     pub url: String,
     pub branch: Option<String>,
     pub tag: Option<String>,
+    pub applied_tag: Option<String>,
+    pub revision: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_hooks: Vec<String>,
+    /// Never runs hooks unless `--trust-hooks` is given
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_hooks: bool,
+    /// Answers of the variables declared by skeleton
+    #[serde(default, skip_serializing_if = "Table::is_empty")]
+    pub vars: Table,
+    /// Whether each component of skeleton is enabled
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<String, bool>,
+    #[serde(default, rename = "strategy", skip_serializing_if = "Vec::is_empty")]
+    pub strategies: Vec<Rule>,
+    /// Applied revisions of the parent skeletons from the nearest one
+    #[serde(default, rename = "layer", skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
+    /// Keys unknown to this version, which are preserved on save
+    #[serde(flatten)]
+    pub extra: Table,
+    #[serde(skip)]
+    overrides: Overrides,
+}
+
+/// Values layered over `.gitskel.toml`, which are never written by `Config::save`
+#[derive(Clone, Debug, Default)]
+struct Overrides {
+    /// Values written in `.gitskel.toml`
+    base: Table,
+    /// Overriding values and their origins by the dotted key like `vars.name`
+    values: BTreeMap<String, (toml::Value, &'static str)>,
+    /// Keys set explicitly by the command, which are saved even if the values are the same as the overriding values
+    changed: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layer {
+    pub url: String,
     pub revision: String,
 }
 
 impl Config {
-    pub fn new<T: AsRef<str>>(url: T, branch: Option<T>, tag: Option<T>, commit: &Commit) -> Self {
+    pub fn new<T: AsRef<str>>(url: T, branch: Option<T>, tag: Option<T>, revision: &str) -> Self {
         Config {
+            version: CONFIG_VERSION,
             url: String::from(url.as_ref()),
             branch: branch.map(|x| String::from(x.as_ref())),
             tag: tag.map(|x| String::from(x.as_ref())),
-            revision: format!("{}", commit.id()),
+            applied_tag: None,
+            revision: String::from(revision),
+            trusted_hooks: Vec::new(),
+            no_hooks: false,
+            vars: Table::new(),
+            components: BTreeMap::new(),
+            strategies: Vec::new(),
+            layers: Vec::new(),
+            extra: Table::new(),
+            overrides: Overrides::default(),
         }
     }
 
     pub fn set_branch(&mut self, branch: &str) {
         self.branch = Some(String::from(branch));
         self.tag = None;
+        self.applied_tag = None;
+        self.mark_changed("branch");
+        self.mark_changed("tag");
     }
 
     pub fn set_tag(&mut self, tag: &str) {
         self.branch = None;
         self.tag = Some(String::from(tag));
+        self.applied_tag = None;
+        self.mark_changed("branch");
+        self.mark_changed("tag");
+    }
+
+    /// Records that the key is set explicitly by the command, so that it is saved even if it is overridden.
+    pub fn mark_changed(&mut self, key: &str) {
+        self.overrides.changed.insert(String::from(key));
+    }
+
+    pub fn set_applied_tag(&mut self, tag: Option<String>) {
+        self.applied_tag = tag;
     }
 
-    pub fn set_commit(&mut self, commit: &Commit) {
-        self.revision = format!("{}", commit.id());
+    pub fn trust_hooks(&mut self, hooks: Vec<String>) {
+        for hook in hooks {
+            if !self.trusted_hooks.contains(&hook) {
+                self.trusted_hooks.push(hook);
+            }
+        }
+    }
+
+    pub fn set_revision(&mut self, revision: &str) {
+        self.revision = String::from(revision);
+    }
+
+    pub fn set_layers(&mut self, layers: &[Layer]) {
+        self.layers = layers.to_vec();
+    }
+
+    /// Returns the value of the key like `branch` or `vars.name`.
+    pub fn get(&self, key: &str) -> Result<Option<toml::Value>, Error> {
+        let value = toml::Value::try_from(self)?;
+        let mut value = Some(&value);
+        for name in key.split('.') {
+            value = value.and_then(|x| x.get(name));
+        }
+        Ok(value.cloned())
     }
 
+    /// Returns where the value of the key comes from.
+    pub fn origin(&self, key: &str) -> &'static str {
+        match self.overrides.values.get(key) {
+            Some((_, origin)) => origin,
+            None => ORIGIN_CONFIG,
+        }
+    }
+
+    /// Returns all values by the dotted key.
+    pub fn entries(&self) -> Result<Vec<(String, toml::Value)>, Error> {
+        let mut ret = Vec::new();
+        flatten("", &Table::try_from(self)?, &mut ret);
+        Ok(ret)
+    }
+
+    /// Sets the value of the key with type checking.
+    ///
+    /// `strategy` and `layer` can't be set because they are arrays of tables.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let (table, name) = match key.split_once('.') {
+            Some((x, y)) => (x, Some(y)),
+            None => (key, None),
+        };
+        match (table, name) {
+            ("url", None) => self.url = String::from(value),
+            ("branch", None) => self.set_branch(value),
+            ("tag", None) => self.set_tag(value),
+            ("revision", None) => {
+                if !is_revision(value) {
+                    bail!(ErrorKind::ConfigValueInvalid(String::from(key)));
+                }
+                self.set_revision(value);
+            }
+            ("no_hooks", None) => match value.parse::<bool>() {
+                Ok(x) => self.no_hooks = x,
+                Err(_) => bail!(ErrorKind::ConfigValueInvalid(String::from(key))),
+            },
+            ("trusted_hooks", None) => {
+                let hooks = toml::from_str::<Table>(&format!("v = {}", value))
+                    .ok()
+                    .and_then(|x| x.get("v").cloned())
+                    .and_then(|x| x.try_into::<Vec<String>>().ok());
+                match hooks {
+                    Some(x) => self.trusted_hooks = x,
+                    None => bail!(ErrorKind::ConfigValueInvalid(String::from(key))),
+                }
+            }
+            ("vars", Some(name)) => {
+                // The type is inferred as TOML, and the value is string if it is not valid TOML
+                let value = toml::from_str::<Table>(&format!("v = {}", value))
+                    .ok()
+                    .and_then(|x| x.get("v").cloned())
+                    .unwrap_or_else(|| toml::Value::from(value));
+                self.vars.insert(String::from(name), value);
+            }
+            ("components", Some(name)) => match value.parse::<bool>() {
+                Ok(x) => {
+                    self.components.insert(String::from(name), x);
+                }
+                Err(_) => bail!(ErrorKind::ConfigValueInvalid(String::from(key))),
+            },
+            _ => bail!(ErrorKind::ConfigKeyInvalid(String::from(key))),
+        }
+        self.mark_changed(key);
+        Ok(())
+    }
+
+    /// Loads `.gitskel.toml`, and layers `[skel]` section of `.git/config` and `.gitskel.local.toml` over it.
     pub fn load(tgt: &Repository) -> Result<Config, Error> {
         let tgt_root = PathBuf::from(tgt.workdir().unwrap());
-        let config_path = tgt_root.join(".gitskel.toml");
+        let config_path = tgt_root.join(ORIGIN_CONFIG);
+        let local_path = tgt_root.join(ORIGIN_LOCAL);
 
-        fn load_file(path: &Path) -> Result<Config, Error> {
+        fn load_file(path: &Path) -> Result<Table, Error> {
             let mut f = fs::File::open(path)?;
             let mut s = String::new();
             let _ = f.read_to_string(&mut s);
-            let config = toml::from_str(&s)?;
+            let value = toml::from_str(&s)?;
+            Ok(value)
+        }
+
+        fn load_config(path: &Path, overrides: Overrides) -> Result<Config, Error> {
+            let mut value = load_file(path)?;
+            migrate(&mut value)?;
+            let base = value.clone();
+            for (key, (x, _)) in &overrides.values {
+                insert(&mut value, key, x.clone());
+            }
+            let mut config: Config = value.try_into()?;
+            config.overrides = Overrides { base, ..overrides };
             Ok(config)
         }
 
-        let config = load_file(&config_path).context(ErrorKind::ConfigLoad(
+        let mut overrides = Overrides::default();
+        for (key, value) in load_git(tgt)? {
+            overrides.values.insert(key, (value, ORIGIN_GIT));
+        }
+        if local_path.exists() {
+            let local = load_file(&local_path).context(ErrorKind::ConfigLoad(
+                local_path.to_string_lossy().to_string(),
+            ))?;
+            let mut values = Vec::new();
+            flatten("", &local, &mut values);
+            for (key, value) in values {
+                overrides.values.insert(key, (value, ORIGIN_LOCAL));
+            }
+        }
+
+        let config = load_config(&config_path, overrides).context(ErrorKind::ConfigLoad(
             config_path.to_string_lossy().to_string(),
         ))?;
         Ok(config)
@@ -58,11 +262,25 @@ impl Config {
 
     pub fn save(&self, tgt: &Repository) -> Result<(), Error> {
         let tgt_root = PathBuf::from(tgt.workdir().unwrap());
-        let config_path = tgt_root.join(".gitskel.toml");
+        let config_path = tgt_root.join(ORIGIN_CONFIG);
 
-        fs::write(&config_path, toml::to_string(self)?).context(ErrorKind::ConfigSave(
+        fn save_file(config: &Config, path: &Path) -> Result<(), Error> {
+            let mut value = Table::try_from(config)?;
+            config.overrides.restore(&mut value);
+            let new = toml::to_string(&value)?;
+            let s = match fs::read_to_string(path) {
+                Ok(old) => edit(&old, &new)?,
+                Err(_) => new,
+            };
+            fs::write(path, s)?;
+            Ok(())
+        }
+
+        save_file(self, &config_path).context(ErrorKind::ConfigSave(
             config_path.to_string_lossy().to_string(),
         ))?;
+        // Loading never writes the repository, so the local override file is excluded when saving
+        exclude_local(tgt)?;
 
         Ok(())
     }
@@ -89,3 +307,394 @@ impl Config {
         }
     }
 }
+
+impl Overrides {
+    /// Restores the overridden values to the values of `.gitskel.toml`.
+    ///
+    /// The values set or changed by the command are kept to be saved.
+    fn restore(&self, value: &mut Table) {
+        for (key, (x, _)) in &self.values {
+            if self.changed.contains(key) || get(value, key) != Some(x) {
+                continue;
+            }
+            match get(&self.base, key) {
+                Some(base) => insert(value, key, base.clone()),
+                None => remove(value, key),
+            }
+        }
+    }
+}
+
+/// Adds the local override file to `.git/info/exclude` if it is neither ignored nor tracked.
+fn exclude_local(tgt: &Repository) -> Result<(), Error> {
+    let local = Path::new(ORIGIN_LOCAL);
+    if tgt.is_path_ignored(local)? || tgt.index()?.get_path(local, 0).is_some() {
+        return Ok(());
+    }
+
+    let path = tgt.commondir().join("info").join("exclude");
+    let mut s = fs::read_to_string(&path).unwrap_or_default();
+    if !s.is_empty() && !s.ends_with('\n') {
+        s.push('\n');
+    }
+    s.push_str(&format!("/{}\n", ORIGIN_LOCAL));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, s)?;
+    Ok(())
+}
+
+/// Loads the overriding values from `[skel]` section of `.git/config` of the repository.
+fn load_git(tgt: &Repository) -> Result<Vec<(String, toml::Value)>, Error> {
+    let config = match tgt.config()?.open_level(ConfigLevel::Local) {
+        Ok(mut x) => x.snapshot()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut ret = Vec::new();
+    for key in ["url", "branch", "tag"] {
+        if let Ok(x) = config.get_string(&format!("skel.{}", key)) {
+            ret.push((String::from(key), toml::Value::from(x)));
+        }
+    }
+    if let Ok(x) = config.get_bool("skel.noHooks") {
+        ret.push((String::from("no_hooks"), toml::Value::from(x)));
+    }
+    Ok(ret)
+}
+
+/// Collects the values of the table by the dotted key.
+///
+/// Arrays are not expanded, so `strategy` and `layer` are collected as a whole.
+fn flatten(prefix: &str, table: &Table, ret: &mut Vec<(String, toml::Value)>) {
+    for (name, value) in table {
+        let key = format!("{}{}", prefix, name);
+        match value {
+            toml::Value::Table(x) => flatten(&format!("{}.", key), x, ret),
+            x => ret.push((key, x.clone())),
+        }
+    }
+}
+
+fn get<'a>(table: &'a Table, key: &str) -> Option<&'a toml::Value> {
+    match key.split_once('.') {
+        Some((x, rest)) => get(table.get(x)?.as_table()?, rest),
+        None => table.get(key),
+    }
+}
+
+fn insert(table: &mut Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((x, rest)) => {
+            let child = table
+                .entry(x)
+                .or_insert_with(|| toml::Value::Table(Table::new()));
+            if !child.is_table() {
+                *child = toml::Value::Table(Table::new());
+            }
+            if let toml::Value::Table(child) = child {
+                insert(child, rest, value);
+            }
+        }
+        None => {
+            table.insert(String::from(key), value);
+        }
+    }
+}
+
+fn remove(table: &mut Table, key: &str) {
+    match key.split_once('.') {
+        Some((x, rest)) => {
+            if let Some(toml::Value::Table(child)) = table.get_mut(x) {
+                remove(child, rest);
+                if child.is_empty() {
+                    table.remove(x);
+                }
+            }
+        }
+        None => {
+            table.remove(key);
+        }
+    }
+}
+
+/// Returns whether the revision is a commit id, a dirty commit id or an archive digest.
+fn is_revision(revision: &str) -> bool {
+    let is_hex = |x: &str, len: usize| x.len() == len && x.chars().all(|c| c.is_ascii_hexdigit());
+    if let Some(x) = revision.strip_prefix("sha256:") {
+        is_hex(x, 64)
+    } else {
+        is_hex(revision.trim_end_matches("-dirty"), 40)
+    }
+}
+
+/// Edits the old document to the new document in place.
+///
+/// Only the changed values are rewritten, so that comments, ordering and formatting of the old document are kept.
+pub fn edit(old: &str, new: &str) -> Result<String, Error> {
+    let mut old: DocumentMut = old.parse()?;
+    let new: DocumentMut = new.parse()?;
+    edit_table(old.as_table_mut(), new.as_table());
+    Ok(old.to_string())
+}
+
+fn edit_table(old: &mut toml_edit::Table, new: &toml_edit::Table) {
+    let removed: Vec<_> = old
+        .iter()
+        .map(|(key, _)| String::from(key))
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in removed {
+        old.remove(&key);
+    }
+
+    for (key, item) in new.iter() {
+        match (old.get_mut(key), item) {
+            (Some(Item::Table(x)), Item::Table(y)) => edit_table(x, y),
+            (Some(x), y) if !is_same(x, y) => {
+                let decor = x.as_value().map(|v| v.decor().clone());
+                *x = y.clone();
+                if let (Some(decor), Some(value)) = (decor, x.as_value_mut()) {
+                    *value.decor_mut() = decor;
+                }
+            }
+            (Some(_), _) => (),
+            (None, y) => {
+                old.insert(key, y.clone());
+            }
+        }
+    }
+}
+
+/// Returns whether the items have the same value regardless of formatting.
+fn is_same(x: &Item, y: &Item) -> bool {
+    let value = |item: &Item| {
+        let mut doc = DocumentMut::new();
+        doc.insert("v", item.clone());
+        toml::from_str::<Table>(&doc.to_string()).ok()
+    };
+    value(x).is_some() && value(x) == value(y)
+}
+
+/// Migrates `.gitskel.toml` of older versions to the current version.
+fn migrate(value: &mut Table) -> Result<(), Error> {
+    // `version` was introduced at version 1
+    let version = match value.get("version") {
+        Some(x) => match x.as_integer() {
+            Some(x) if x >= 0 => x,
+            _ => bail!("invalid version: {}", x),
+        },
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        return Err(ErrorKind::ConfigVersion(version).into());
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(value)?;
+    }
+    value.insert(String::from("version"), toml::Value::from(CONFIG_VERSION));
+    Ok(())
+}
+
+/// Version 0 is the layout without `version`, which is compatible with version 1.
+fn migrate_v0(_value: &mut Table) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let mut value: Table = toml::from_str(
+            r#"
+            url = "https://example.com/skel.git"
+            revision = "f5ca092fecc42c260131f5c42a64f67d4cf58561"
+            future_key = "kept"
+            "#,
+        )
+        .unwrap();
+        migrate(&mut value).unwrap();
+        let config: Config = value.try_into().unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(saved.starts_with("version = 1\n"));
+        assert!(saved.contains("future_key = \"kept\""));
+
+        let mut value: Table = toml::from_str("version = 2").unwrap();
+        assert!(migrate(&mut value).is_err());
+    }
+
+    #[test]
+    fn test_get_set() {
+        let mut config: Config = toml::from_str(
+            r#"
+            version = 1
+            url = "https://example.com/skel.git"
+            tag = "v1.*"
+            revision = "f5ca092fecc42c260131f5c42a64f67d4cf58561"
+            "#,
+        )
+        .unwrap();
+
+        config.set("branch", "develop").unwrap();
+        assert_eq!(config.branch.as_deref(), Some("develop"));
+        assert!(config.tag.is_none());
+
+        config.set("vars.count", "3").unwrap();
+        config.set("vars.name", "foo bar").unwrap();
+        config.set("components.docker", "false").unwrap();
+        config.set("no_hooks", "true").unwrap();
+        assert!(config.no_hooks);
+        assert!(config.set("no_hooks", "yes").is_err());
+        assert_eq!(
+            config.get("vars.count").unwrap(),
+            Some(toml::Value::from(3))
+        );
+        assert_eq!(
+            config.get("vars.name").unwrap(),
+            Some(toml::Value::from("foo bar"))
+        );
+        assert_eq!(
+            config.get("components.docker").unwrap(),
+            Some(toml::Value::from(false))
+        );
+        assert_eq!(config.get("tag").unwrap(), None);
+
+        assert!(config.set("components.docker", "yes").is_err());
+        assert!(config.set("revision", "master").is_err());
+        assert!(config.set("strategy", "[]").is_err());
+        assert!(config.set("unknown", "x").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let base: Table = toml::from_str(
+            r#"
+            version = 1
+            url = "https://example.com/skel.git"
+            revision = "f5ca092fecc42c260131f5c42a64f67d4cf58561"
+
+            [vars]
+            name = "foo"
+            "#,
+        )
+        .unwrap();
+        let local: Table = toml::from_str(
+            r#"
+            url = "/mnt/mirror/skel"
+            no_hooks = true
+
+            [vars]
+            name = "bar"
+            "#,
+        )
+        .unwrap();
+
+        let mut values = Vec::new();
+        flatten("", &local, &mut values);
+        let overrides = Overrides {
+            base: base.clone(),
+            values: values
+                .into_iter()
+                .map(|(key, x)| (key, (x, ORIGIN_LOCAL)))
+                .collect(),
+            changed: BTreeSet::new(),
+        };
+        let mut value = base.clone();
+        for (key, (x, _)) in &overrides.values {
+            insert(&mut value, key, x.clone());
+        }
+        let mut config: Config = value.try_into().unwrap();
+        config.overrides = overrides;
+        assert_eq!(config.url, "/mnt/mirror/skel");
+        assert!(config.no_hooks);
+        assert_eq!(config.origin("vars.name"), ORIGIN_LOCAL);
+        assert_eq!(config.origin("revision"), ORIGIN_CONFIG);
+
+        // Only the value changed by the command is saved
+        config.set("vars.name", "baz").unwrap();
+        let mut value = Table::try_from(&config).unwrap();
+        config.overrides.restore(&mut value);
+        assert_eq!(get(&value, "url"), get(&base, "url"));
+        assert_eq!(get(&value, "no_hooks"), None);
+        assert_eq!(get(&value, "vars.name"), Some(&toml::Value::from("baz")));
+
+        // The value set explicitly is saved even if it is the same as the overriding value
+        config.set("url", "/mnt/mirror/skel").unwrap();
+        let mut value = Table::try_from(&config).unwrap();
+        config.overrides.restore(&mut value);
+        assert_eq!(
+            get(&value, "url"),
+            Some(&toml::Value::from("/mnt/mirror/skel"))
+        );
+    }
+
+    #[test]
+    fn test_exclude_local() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let exclude = repo.path().join("info").join("exclude");
+        let excluded = || {
+            fs::read_to_string(&exclude)
+                .unwrap_or_default()
+                .lines()
+                .any(|x| x == "/.gitskel.local.toml")
+        };
+
+        Config::new("/mnt/skel", None, None, "0000000")
+            .save(&repo)
+            .unwrap();
+        assert!(excluded());
+
+        // Loading doesn't write the repository even if the local override file is not excluded
+        fs::remove_file(&exclude).unwrap();
+        fs::write(
+            dir.path().join(ORIGIN_LOCAL),
+            "url = \"/mnt/mirror/skel\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&repo).unwrap();
+        assert_eq!(config.url, "/mnt/mirror/skel");
+        assert!(!excluded());
+
+        config.save(&repo).unwrap();
+        assert!(excluded());
+    }
+
+    #[test]
+    fn test_edit() {
+        let old = r#"# managed by git-skel
+url = "https://example.com/skel.git"
+# pinned until the migration is done
+branch = "v1" # see #123
+revision = "aaaa"
+
+[vars]
+name = "foo"
+"#;
+        let new = r#"url = "https://example.com/skel.git"
+branch = "v1"
+revision = "bbbb"
+applied_tag = "v1.1.0"
+
+[vars]
+name = "foo"
+"#;
+        let expected = r#"# managed by git-skel
+url = "https://example.com/skel.git"
+# pinned until the migration is done
+branch = "v1" # see #123
+revision = "bbbb"
+applied_tag = "v1.1.0"
+
+[vars]
+name = "foo"
+"#;
+        assert_eq!(edit(old, new).unwrap(), expected);
+    }
+}
@@ -0,0 +1,147 @@
+use failure::Error;
+use git2::{AttrCheckFlags, AttrValue, Repository};
+use std::borrow::Cow;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Text {
+    Set,
+    Unset,
+    Auto,
+}
+
+/// Line ending settings of git config in target repository
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EolConfig {
+    autocrlf: String,
+    core_eol: String,
+}
+
+impl EolConfig {
+    pub fn new(tgt: &Repository) -> Result<EolConfig, Error> {
+        let config = tgt.config()?.snapshot()?;
+        let get = |name: &str| {
+            config
+                .get_string(name)
+                .map(|x| x.to_lowercase())
+                .unwrap_or_default()
+        };
+        Ok(EolConfig {
+            autocrlf: get("core.autocrlf"),
+            core_eol: get("core.eol"),
+        })
+    }
+}
+
+/// Line ending conversion of a file in target repository
+///
+/// This follows `text` and `eol` attributes of `.gitattributes`, and `core.autocrlf` and `core.eol` of git config.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conversion {
+    text: Text,
+    crlf: bool,
+}
+
+impl Conversion {
+    pub fn new(tgt: &Repository, config: &EolConfig, path: &Path) -> Result<Conversion, Error> {
+        let autocrlf = config.autocrlf.as_str();
+        let core_eol = config.core_eol.as_str();
+
+        let flags = AttrCheckFlags::FILE_THEN_INDEX;
+        let text = AttrValue::from_string(tgt.get_attr(path, "text", flags)?);
+        let eol = AttrValue::from_string(tgt.get_attr(path, "eol", flags)?);
+
+        let autocrlf_enabled = autocrlf == "true" || autocrlf == "input";
+        let text = match text {
+            AttrValue::True => Text::Set,
+            AttrValue::False => Text::Unset,
+            AttrValue::String("auto") => Text::Auto,
+            _ => match eol {
+                AttrValue::String(_) => Text::Set,
+                _ if autocrlf_enabled => Text::Auto,
+                _ => Text::Unset,
+            },
+        };
+
+        let crlf = match eol {
+            AttrValue::String("crlf") => true,
+            AttrValue::String("lf") => false,
+            _ if autocrlf == "true" => true,
+            _ if autocrlf == "input" => false,
+            _ => core_eol == "crlf" || (core_eol == "native" && cfg!(target_os = "windows")),
+        };
+
+        Ok(Conversion { text, crlf })
+    }
+
+    fn is_text(&self, content: &[u8]) -> bool {
+        match self.text {
+            Text::Set => true,
+            Text::Unset => false,
+            Text::Auto => !content.contains(&0),
+        }
+    }
+
+    /// Converts CRLF to LF if the content is text.
+    pub fn normalize<'a>(&self, content: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.is_text(content) && content.windows(2).any(|x| x == b"\r\n") {
+            let mut ret = Vec::with_capacity(content.len());
+            let mut iter = content.iter().peekable();
+            while let Some(c) = iter.next() {
+                if *c == b'\r' && iter.peek() == Some(&&b'\n') {
+                    continue;
+                }
+                ret.push(*c);
+            }
+            Cow::Owned(ret)
+        } else {
+            Cow::Borrowed(content)
+        }
+    }
+
+    /// Converts line endings of the content to the line endings of the working tree.
+    pub fn apply(&self, content: &[u8]) -> Vec<u8> {
+        let normalized = self.normalize(content);
+        if self.crlf && self.is_text(content) {
+            let mut ret = Vec::with_capacity(normalized.len());
+            for c in normalized.iter() {
+                if *c == b'\n' {
+                    ret.push(b'\r');
+                }
+                ret.push(*c);
+            }
+            ret
+        } else {
+            normalized.into_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        let crlf = Conversion {
+            text: Text::Auto,
+            crlf: true,
+        };
+        assert_eq!(&*crlf.normalize(b"a\r\nb\n"), b"a\nb\n");
+        assert_eq!(crlf.apply(b"a\r\nb\n"), b"a\r\nb\r\n");
+        assert_eq!(crlf.apply(b"a\nb\0\n"), b"a\nb\0\n");
+
+        let lf = Conversion {
+            text: Text::Set,
+            crlf: false,
+        };
+        assert_eq!(lf.apply(b"a\r\nb\r\n"), b"a\nb\n");
+
+        let binary = Conversion {
+            text: Text::Unset,
+            crlf: true,
+        };
+        assert_eq!(&*binary.normalize(b"a\r\nb\n"), b"a\r\nb\n");
+        assert_eq!(binary.apply(b"a\r\nb\n"), b"a\r\nb\n");
+    }
+}
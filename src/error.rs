@@ -4,18 +4,100 @@ use failure::Fail;
 pub enum ErrorKind {
     #[fail(display = "failed to discover current repository")]
     RepoDiscover,
+    #[fail(display = "failed to open repository: {}", 0)]
+    RepoOpen(String),
     #[fail(display = "failed to clone target repository: {}", 0)]
     RepoClone(String),
+    #[fail(
+        display = "failed to authenticate by ssh-agent\n       Start ssh-agent and register your key by `ssh-add`."
+    )]
+    AuthSshAgent,
+    #[fail(
+        display = "failed to authenticate by ssh key: {}\n       Put your key to `~/.ssh` or set the path to `GIT_SKEL_SSH_KEY`.\n       If the key is encrypted, set the passphrase to `GIT_SKEL_SSH_PASSPHRASE`.",
+        0
+    )]
+    AuthSshKey(String),
+    #[fail(
+        display = "failed to authenticate by token: {}\n       Check that the token is valid and can read the repository.",
+        0
+    )]
+    AuthToken(String),
+    #[fail(
+        display = "failed to authenticate by git credential helper\n       Configure `credential.helper` by `git config`, or set the token to `GIT_SKEL_TOKEN`."
+    )]
+    AuthCredentialHelper,
+    #[fail(
+        display = "no credential is available\n       Use ssh-agent, key files in `~/.ssh`, `credential.helper` or `GIT_SKEL_TOKEN`."
+    )]
+    AuthNoCredential,
     #[fail(display = "failed to find branch: {}", 0)]
     BranchNotFound(String),
     #[fail(display = "failed to find tag: {}", 0)]
     TagNotFound(String),
     #[fail(display = "failed to find revision: {}", 0)]
     RevisionNotFound(String),
+    #[fail(display = "failed to load archive: {}", 0)]
+    ArchiveLoad(String),
+    #[fail(display = "failed to load bundle: {}", 0)]
+    BundleLoad(String),
+    #[fail(display = "invalid bundle: {}", 0)]
+    BundleInvalid(String),
+    #[fail(
+        display = "prerequisite commit of bundle is not found: {}\n       Unpack the previous bundle first.",
+        0
+    )]
+    BundlePrerequisiteNotFound(String),
+    #[fail(display = "failed to find cache directory\n       Set it by `GIT_SKEL_CACHE_DIR`.")]
+    CacheNotFound,
+    #[fail(display = "`--dirty` is available for a local skeleton only: {}", 0)]
+    DirtyNotLocal(String),
+    #[fail(display = "parent skeleton is circular: {}", 0)]
+    ParentCircular(String),
+    #[fail(display = "failed to load user config: {}", 0)]
+    UserConfigLoad(String),
     #[fail(display = "failed to load config: {}", 0)]
     ConfigLoad(String),
+    #[fail(
+        display = "config is created by newer git-skel ( version {} )\n       Update git-skel by `cargo install git-skel`.",
+        0
+    )]
+    ConfigVersion(i64),
+    #[fail(display = "invalid config key: {}", 0)]
+    ConfigKeyInvalid(String),
+    #[fail(display = "config key is not set: {}", 0)]
+    ConfigKeyNotSet(String),
+    #[fail(display = "invalid config value: {}", 0)]
+    ConfigValueInvalid(String),
+    #[fail(display = "{} problems are found in config", 0)]
+    ConfigInvalid(usize),
+    #[fail(display = "invalid url: {}", 0)]
+    UrlInvalid(String),
     #[fail(display = "failed to save config: {}", 0)]
     ConfigSave(String),
+    #[fail(display = "failed to load batch manifest: {}", 0)]
+    BatchLoad(String),
+    #[fail(display = "failed to load skeleton manifest: {}", 0)]
+    ManifestLoad(String),
+    #[fail(
+        display = "skeleton requires git-skel {} or later\n       Update git-skel by `cargo install git-skel`.",
+        0
+    )]
+    VersionRequired(String),
+    #[fail(
+        display = "variable is not set: {}\n       Set it by `--var {}=VALUE` or `--vars-file` option.",
+        0, 0
+    )]
+    VariableRequired(String),
+    #[fail(display = "unknown variable: {}", 0)]
+    VariableUnknown(String),
+    #[fail(display = "invalid value of variable: {}", 0)]
+    VariableInvalid(String),
+    #[fail(display = "component is not found: {}", 0)]
+    ComponentNotFound(String),
+    #[fail(display = "failed to load variables: {}", 0)]
+    VarsLoad(String),
+    #[fail(display = "failed to run hook: {}", 0)]
+    HookFailed(String),
     #[fail(
         display = "aborted bacause\n         - some files are not committed    ( marked by ! )\n         - some files are modified locally ( marked by * )\n       If you will ignore it, use `--force` option."
     )]
@@ -24,6 +106,47 @@ pub enum ErrorKind {
         display = "aborted bacause some files exist ( marked by ! )\n       If you will ignore it, use `--force` option."
     )]
     AbortByExist,
+    #[fail(
+        display = "aborted bacause skeleton hooks are not trusted\n       If you trust them, use `--trust-hooks` option. If you skip them, use `--no-hooks` option."
+    )]
+    HookNotTrusted,
+    #[fail(display = "aborted bacause {} repositories are not updated", 0)]
+    AbortByBatch(usize),
     #[fail(display = "aborted bacause config file exists: {}", 0)]
     AbortByConfigExist(String),
+    #[fail(display = "update is available\n       Run `git skel update` to apply it.")]
+    CheckUpdateAvailable,
+    #[fail(
+        display = "{} files are different from the applied revision\n       Run `git skel update --force` to restore them.",
+        0
+    )]
+    CheckDrift(usize),
+    #[fail(
+        display = "`--output` is required with `--format json` / `--format ndjson`\n       The report and the patches can't share stdout."
+    )]
+    PatchOutputRequired,
+}
+
+impl ErrorKind {
+    /// Exit code of the process ( `git skel check` uses distinct codes for CI )
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::CheckUpdateAvailable => 2,
+            ErrorKind::CheckDrift(_) => 3,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(ErrorKind::CheckUpdateAvailable.exit_code(), 2);
+        assert_eq!(ErrorKind::CheckDrift(3).exit_code(), 3);
+        assert_eq!(ErrorKind::RepoDiscover.exit_code(), 1);
+        assert_eq!(ErrorKind::AbortByModified.exit_code(), 1);
+    }
 }
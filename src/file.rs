@@ -1,69 +1,387 @@
+use crate::block;
+use crate::component::Components;
+use crate::condition::Conditions;
+use crate::config::Config;
+use crate::eol::{Conversion, EolConfig};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::report::{Action, IgnoreSource, Kind, LocalStatus, Reason, Report};
+use crate::strategy::{Rules, Strategy};
+use crate::structured::{self, Format};
+use crate::template::{self, Templates};
+use crate::user::UserConfig;
 use failure::Error;
-use git2::Repository;
-use ignore::gitignore::Gitignore;
+use git2::{FileMode, IndexEntry, IndexTime, MergeFileOptions, Oid, Repository, Tree};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use toml::Table;
 
-pub fn copy(
-    src: &Repository,
-    tgt: &Repository,
-    src_ignore: &Gitignore,
-    tgt_ignore: &Gitignore,
+/// Repositories and settings shared by file operations
+pub struct Context<'a> {
+    pub src: &'a Repository,
+    pub tgt: &'a Repository,
+    pub src_ignore: Gitignore,
+    /// Ignore patterns of the user config, which are matched with skeleton files
+    pub user_ignore: Gitignore,
+    pub tgt_ignore: Gitignore,
+    pub rules: Rules,
+    /// Line ending settings of target repository, which are read once per command
+    pub eol: EolConfig,
+    pub report: &'a Report,
+    pub templates: Templates,
+    /// Template files whose rendered content is changed by variables
+    pub rendered: Vec<PathBuf>,
+    pub conditions: Conditions,
+    pub components: Components,
+    /// Variables and components to be applied
+    pub vars: Table,
+    pub enabled: BTreeMap<String, bool>,
+    /// Variables and components applied before this command, used to detect the files toggled by them
+    pub old_vars: Table,
+    pub old_enabled: BTreeMap<String, bool>,
+    /// Tree of the applied revision of skeleton repository, used as the base of three-way merge
+    pub base: Option<Tree<'a>>,
+    /// Files to be applied, or all files if `None`
+    pub scope: Option<Vec<PathBuf>>,
+}
+
+impl<'a> Context<'a> {
+    /// Builds the settings of file operations from the skeleton manifest and the config.
+    ///
+    /// The variables and components of `config` are used as both the applied and the previous ones,
+    /// and all files are applied without a base. Commands override the fields which differ from them.
+    pub fn new(
+        src: &'a Repository,
+        tgt: &'a Repository,
+        manifest: &Manifest,
+        config: &Config,
+        user: &UserConfig,
+        report: &'a Report,
+    ) -> Result<Context<'a>, Error> {
+        let mut user_ignore = GitignoreBuilder::new(src.workdir().unwrap());
+        for line in &user.ignore {
+            user_ignore.add_line(None, line)?;
+        }
+        Ok(Context {
+            src,
+            tgt,
+            src_ignore: get_ignore(src, &manifest.ignore)?,
+            user_ignore: user_ignore.build()?,
+            tgt_ignore: get_ignore(tgt, &[])?,
+            rules: Rules::new(&config.strategies, &manifest.strategies)?,
+            eol: EolConfig::new(tgt)?,
+            report,
+            templates: Templates::new(&manifest.templates)?,
+            rendered: Vec::new(),
+            conditions: Conditions::new(&manifest.conditions)?,
+            components: Components::new(&manifest.components)?,
+            vars: config.vars.clone(),
+            enabled: config.components.clone(),
+            old_vars: config.vars.clone(),
+            old_enabled: config.components.clone(),
+            base: None,
+            scope: None,
+        })
+    }
+
+    /// Returns true if the file is enabled by conditions and components.
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.conditions.is_included(path, &self.vars)
+            && self.components.is_included(path, &self.enabled)
+    }
+
+    /// Returns true if the file is applied by this command.
+    pub fn is_in_scope(&self, path: &Path) -> bool {
+        self.scope
+            .as_ref()
+            .is_none_or(|x| x.iter().any(|y| y == path))
+    }
+
+    /// Returns true if the file was enabled before this command.
+    pub fn was_included(&self, path: &Path) -> bool {
+        self.conditions.is_included(path, &self.old_vars)
+            && self.components.is_included(path, &self.old_enabled)
+    }
+}
+
+pub fn copy(ctx: &Context, path: &Path, modified: &[&Path], dry_run: bool) -> Result<bool, Error> {
+    let src_root = PathBuf::from(ctx.src.workdir().unwrap());
+    let tgt_root = PathBuf::from(ctx.tgt.workdir().unwrap());
+    let src_path = src_root.join(path);
+    let tgt_path = tgt_root.join(path);
+
+    let ignore = ignore_source(ctx, path);
+
+    let strategy = ctx.rules.get(path);
+    let tgt_exists = path_exists(&tgt_path);
+    let merge = (strategy == Strategy::Merge || strategy == Strategy::Structured)
+        && tgt_exists
+        && !is_symlink(&src_path);
+
+    if strategy == Strategy::Block && !is_symlink(&src_path) {
+        return copy_block(ctx, path, &src_path, &tgt_path, ignore, dry_run);
+    }
+
+    if strategy == Strategy::Structured && merge {
+        if let Some(format) = Format::from_path(path) {
+            return copy_structured(ctx, path, format, &src_path, &tgt_path, ignore, dry_run);
+        }
+    }
+
+    let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+
+    if !is_diff(&src_path, &tgt_path, &conv)? {
+        return Ok(false);
+    }
+
+    // Files of `create-once` are created only at init or when they are new in skeleton,
+    // so that the files deleted by the user are not created again
+    let in_base = ctx.base.as_ref().is_some_and(|x| x.get_path(path).is_ok());
+
+    let status = ctx.tgt.status_file(path);
+    let mut merged = None;
+    let (kind, reason) = if strategy == Strategy::CreateOnce && (tgt_exists || in_base) {
+        (Kind::Keep, Reason::Strategy)
+    } else if ignore.is_some() {
+        (Kind::Ignore, Reason::Ignored)
+    } else if strategy == Strategy::Overwrite {
+        (Kind::Copy, Reason::Skeleton)
+    } else if merge {
+        let (content, conflict) = merge_file(ctx, path, &src_path, &tgt_path)?;
+        merged = Some(content);
+        // Uncommitted changes are not merged silently, as the same as plain copies
+        if status.as_ref().is_ok_and(|x| !x.is_empty()) {
+            (Kind::Merge, Reason::Uncommitted)
+        } else if conflict {
+            (Kind::Merge, Reason::Conflict)
+        } else {
+            (Kind::Merge, Reason::Skeleton)
+        }
+    } else if let Ok(status) = status {
+        if status.is_empty() {
+            if !modified.contains(&path) {
+                (Kind::Copy, Reason::LocallyModified)
+            } else {
+                (Kind::Copy, Reason::Skeleton)
+            }
+        } else {
+            (Kind::Copy, Reason::Uncommitted)
+        }
+    } else if tgt_exists {
+        (Kind::Copy, Reason::Untracked)
+    } else {
+        (Kind::Copy, Reason::Skeleton)
+    };
+    let action = Action::new(
+        path,
+        kind,
+        reason,
+        ignore,
+        LocalStatus::new(&status, tgt_exists),
+        strategy,
+    );
+    let warn = action.warn();
+
+    if !dry_run && action.is_change() {
+        if let Some(parent) = tgt_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        if let Some(content) = merged {
+            fs::write(&tgt_path, conv.apply(&content))?;
+        } else if is_symlink(&src_path) {
+            let link_path = fs::read_link(&src_path)?;
+            symlink(&link_path, &tgt_path)?;
+        } else {
+            fs::copy(&src_path, &tgt_path)?;
+            let content = fs::read(&src_path)?;
+            let converted = conv.apply(&content);
+            if converted != content {
+                fs::write(&tgt_path, converted)?;
+            }
+        }
+    }
+    ctx.report.action(action);
+
+    Ok(dry_run && warn)
+}
+
+fn copy_block(
+    ctx: &Context,
     path: &Path,
-    modified: &Vec<&Path>,
+    src_path: &Path,
+    tgt_path: &Path,
+    ignore: Option<IgnoreSource>,
     dry_run: bool,
 ) -> Result<bool, Error> {
-    let src_root = PathBuf::from(src.workdir().unwrap());
-    let tgt_root = PathBuf::from(tgt.workdir().unwrap());
-    let src_path = src_root.join(&path);
-    let tgt_path = tgt_root.join(&path);
-
-    let src_ignored = is_ignore(src_ignore, path);
-    let tgt_ignored = is_ignore(tgt_ignore, path);
-    let ignored = src_ignored || tgt_ignored;
-
-    let mut warn = false;
-    if is_diff(&src_path, &tgt_path)? {
-        if dry_run {
-            let status = tgt.status_file(&path);
-            let indicator = if ignored {
-                " ignore"
-            } else if let Ok(status) = status {
-                if status.is_empty() {
-                    if !modified.contains(&path) {
-                        warn = true;
-                        "*copy  "
-                    } else {
-                        " copy  "
-                    }
-                } else {
-                    warn = true;
-                    "!copy  "
-                }
-            } else if path_exists(&tgt_path) {
-                warn = true;
-                "!copy  "
-            } else {
-                " copy  "
-            };
-            println!("  {}: {}", indicator, path.to_string_lossy());
-        } else if !ignored {
-            if let Some(parent) = tgt_path.parent() {
-                if !parent.exists() {
-                    fs::create_dir_all(&parent)?;
-                }
-            }
-            if fs::symlink_metadata(&src_path)?.file_type().is_symlink() {
-                let link_path = fs::read_link(&src_path)?;
-                symlink(&link_path, &tgt_path)?;
-            } else {
-                fs::copy(&src_path, &tgt_path)?;
+    let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+    let tgt_exists = path_exists(tgt_path);
+    let block = block::extract(&read_text(src_path, &conv)?);
+    let tgt_text = if tgt_exists {
+        read_text(tgt_path, &conv)?
+    } else {
+        String::new()
+    };
+    let new_text = block::replace(&tgt_text, &block);
+
+    if new_text == tgt_text {
+        return Ok(false);
+    }
+
+    let (kind, reason) = if ignore.is_some() {
+        (Kind::Ignore, Reason::Ignored)
+    } else if is_block_modified(ctx, path, &tgt_text)? {
+        (Kind::Block, Reason::LocallyModified)
+    } else {
+        (Kind::Block, Reason::Skeleton)
+    };
+    let status = LocalStatus::new(&ctx.tgt.status_file(path), tgt_exists);
+    let action = Action::new(path, kind, reason, ignore, status, Strategy::Block);
+    let warn = action.warn();
+
+    if !dry_run && action.is_change() {
+        if let Some(parent) = tgt_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)?;
             }
         }
+        fs::write(tgt_path, conv.apply(new_text.as_bytes()))?;
     }
+    ctx.report.action(action);
+
+    Ok(dry_run && warn)
+}
 
-    Ok(warn)
+/// Checks whether the managed block of target file is different from the block of the applied revision.
+fn is_block_modified(ctx: &Context, path: &Path, tgt_text: &str) -> Result<bool, Error> {
+    let local = match block::get(tgt_text) {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let base = match base_content(ctx, path)? {
+        Some(x) => block::extract(&String::from_utf8_lossy(&x)),
+        None => return Ok(false),
+    };
+    Ok(local != base)
+}
+
+/// Merges the skeleton file into the target file at key level.
+///
+/// TOML files are edited in place, and JSON and YAML files are re-serialized.
+fn copy_structured(
+    ctx: &Context,
+    path: &Path,
+    format: Format,
+    src_path: &Path,
+    tgt_path: &Path,
+    ignore: Option<IgnoreSource>,
+    dry_run: bool,
+) -> Result<bool, Error> {
+    let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+    let base = base_content(ctx, path)?;
+    let ours = fs::read(tgt_path)?;
+    let theirs = fs::read(src_path)?;
+
+    let (merged, conflict) = match structured::merge(format, base.as_deref(), &ours, &theirs)? {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+
+    let status = ctx.tgt.status_file(path);
+    let (kind, reason) = if ignore.is_some() {
+        (Kind::Ignore, Reason::Ignored)
+    } else if status.as_ref().is_ok_and(|x| !x.is_empty()) {
+        (Kind::Merge, Reason::Uncommitted)
+    } else if conflict {
+        (Kind::Merge, Reason::Conflict)
+    } else {
+        (Kind::Merge, Reason::Skeleton)
+    };
+    let action = Action::new(
+        path,
+        kind,
+        reason,
+        ignore,
+        LocalStatus::new(&status, true),
+        Strategy::Structured,
+    );
+    let warn = action.warn();
+
+    if !dry_run && action.is_change() {
+        fs::write(tgt_path, conv.apply(&merged))?;
+    }
+    ctx.report.action(action);
+
+    Ok(dry_run && warn)
+}
+
+/// Returns the content of the file at the applied revision of skeleton repository.
+///
+/// Template files are rendered by the variables applied before this command, as the same as the project file.
+fn base_content(ctx: &Context, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    let entry = match ctx.base.as_ref().and_then(|x| x.get_path(path).ok()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let content = Vec::from(ctx.src.find_blob(entry.id())?.content());
+    if entry.filemode() == i32::from(FileMode::Link) || !ctx.templates.is_match(path) {
+        return Ok(Some(content));
+    }
+
+    // Binary files and templates which can't be rendered by the old variables are compared as they are
+    let rendered = std::str::from_utf8(&content)
+        .ok()
+        .and_then(|x| template::render(x, &ctx.old_vars).ok());
+    Ok(Some(rendered.map(String::into_bytes).unwrap_or(content)))
+}
+
+/// Merges the changes of skeleton file into the target file, and returns the merged content and whether conflicts exist.
+fn merge_file(
+    ctx: &Context,
+    path: &Path,
+    src_path: &Path,
+    tgt_path: &Path,
+) -> Result<(Vec<u8>, bool), Error> {
+    let base = ctx
+        .src
+        .blob(&base_content(ctx, path)?.unwrap_or_default())?;
+    let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+    let ours = ctx.src.blob(&conv.normalize(&fs::read(tgt_path)?))?;
+    let theirs = ctx.src.blob(&conv.normalize(&fs::read(src_path)?))?;
+
+    let mut opts = MergeFileOptions::new();
+    opts.ancestor_label("base")
+        .our_label("project")
+        .their_label("skeleton");
+
+    let result = ctx.src.merge_file_from_index(
+        &index_entry(path, base),
+        &index_entry(path, ours),
+        &index_entry(path, theirs),
+        Some(&mut opts),
+    )?;
+
+    Ok((Vec::from(result.content()), !result.is_automergeable()))
+}
+
+fn index_entry(path: &Path, id: Oid) -> IndexEntry {
+    IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0o100644,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id,
+        flags: 0,
+        flags_extended: 0,
+        path: Vec::from(path.to_string_lossy().replace('\\', "/").as_bytes()),
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -82,7 +400,37 @@ fn symlink(src: &Path, dst: &Path) -> Result<(), Error> {
     Ok(())
 }
 
-fn is_diff(src_path: &Path, tgt_path: &Path) -> Result<bool, Error> {
+/// Returns the normalized content of the project file if it should be exported to skeleton repository.
+///
+/// Only files owned by skeleton ( `copy`, `overwrite` and `never-delete` strategy ), not ignored, not templates and modified are exported.
+pub fn export(ctx: &Context, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    let src_root = PathBuf::from(ctx.src.workdir().unwrap());
+    let tgt_root = PathBuf::from(ctx.tgt.workdir().unwrap());
+    let src_path = src_root.join(path);
+    let tgt_path = tgt_root.join(path);
+
+    let owned = matches!(
+        ctx.rules.get(path),
+        Strategy::Copy | Strategy::Overwrite | Strategy::NeverDelete
+    );
+    if !owned || ignore_source(ctx, path).is_some() || ctx.templates.is_match(path) {
+        return Ok(None);
+    }
+    if !path_exists(&tgt_path) || is_symlink(&src_path) || is_symlink(&tgt_path) {
+        return Ok(None);
+    }
+
+    let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+    if !is_diff(&src_path, &tgt_path, &conv)? {
+        return Ok(None);
+    }
+
+    let content = fs::read(&tgt_path)?;
+    Ok(Some(conv.normalize(&content).into_owned()))
+}
+
+/// Compares the files after line ending normalization of target repository.
+fn is_diff(src_path: &Path, tgt_path: &Path, conv: &Conversion) -> Result<bool, Error> {
     if let Ok(mut src) = fs::File::open(src_path) {
         if let Ok(mut tgt) = fs::File::open(tgt_path) {
             let mut src_buf = Vec::new();
@@ -91,7 +439,7 @@ fn is_diff(src_path: &Path, tgt_path: &Path) -> Result<bool, Error> {
             src.read_to_end(&mut src_buf)?;
             tgt.read_to_end(&mut tgt_buf)?;
 
-            Ok(src_buf != tgt_buf)
+            Ok(conv.normalize(&src_buf) != conv.normalize(&tgt_buf))
         } else {
             Ok(true)
         }
@@ -100,48 +448,131 @@ fn is_diff(src_path: &Path, tgt_path: &Path) -> Result<bool, Error> {
     }
 }
 
+fn read_text(path: &Path, conv: &Conversion) -> Result<String, Error> {
+    let content = fs::read(path)?;
+    Ok(String::from_utf8(conv.normalize(&content).into_owned())?)
+}
+
 fn is_ignore(ignore: &Gitignore, path: &Path) -> bool {
     ignore.matched(path, false).is_ignore()
 }
 
-pub fn delete(
-    tgt: &Repository,
-    src_ignore: &Gitignore,
-    tgt_ignore: &Gitignore,
+pub fn delete(ctx: &Context, path: &Path, dry_run: bool) -> Result<bool, Error> {
+    let tgt_root = PathBuf::from(ctx.tgt.workdir().unwrap());
+    let tgt_path = tgt_root.join(path);
+
+    let ignore = ignore_source(ctx, path);
+
+    let strategy = ctx.rules.get(path);
+    let keep = strategy == Strategy::CreateOnce || strategy == Strategy::NeverDelete;
+    let tgt_exists = path_exists(&tgt_path);
+
+    if strategy == Strategy::Block && tgt_exists && !is_symlink(&tgt_path) {
+        let conv = Conversion::new(ctx.tgt, &ctx.eol, path)?;
+        return delete_block(ctx, path, &tgt_path, &conv, ignore, dry_run);
+    }
+
+    let status = ctx.tgt.status_file(path);
+    let (kind, reason) = if ignore.is_some() {
+        (Kind::Ignore, Reason::Ignored)
+    } else if keep {
+        (Kind::Keep, Reason::Strategy)
+    } else if let Ok(ref status) = status {
+        if status.is_empty() || strategy == Strategy::Overwrite {
+            (Kind::Delete, Reason::Skeleton)
+        } else {
+            (Kind::Delete, Reason::Uncommitted)
+        }
+    } else if tgt_exists {
+        if strategy == Strategy::Overwrite {
+            (Kind::Delete, Reason::Skeleton)
+        } else {
+            (Kind::Delete, Reason::Untracked)
+        }
+    } else {
+        (Kind::Missing, Reason::NotFound)
+    };
+    let action = Action::new(
+        path,
+        kind,
+        reason,
+        ignore,
+        LocalStatus::new(&status, tgt_exists),
+        strategy,
+    );
+    let warn = action.warn();
+
+    if !dry_run && action.is_change() && tgt_exists {
+        remove_recursive(&tgt_path)?;
+    }
+    ctx.report.action(action);
+
+    Ok(dry_run && warn)
+}
+
+fn delete_block(
+    ctx: &Context,
     path: &Path,
+    tgt_path: &Path,
+    conv: &Conversion,
+    ignore: Option<IgnoreSource>,
     dry_run: bool,
 ) -> Result<bool, Error> {
-    let tgt_root = PathBuf::from(tgt.workdir().unwrap());
-    let tgt_path = tgt_root.join(&path);
-
-    let src_ignored = is_ignore(src_ignore, path);
-    let tgt_ignored = is_ignore(tgt_ignore, path);
-    let ignored = src_ignored || tgt_ignored;
-
-    let mut warn = false;
-    if dry_run {
-        let status = tgt.status_file(&path);
-        let indicator = if ignored {
-            " ignore"
-        } else if let Ok(status) = status {
-            if status.is_empty() {
-                " delete"
-            } else {
-                warn = true;
-                "!delete"
-            }
-        } else if path_exists(&tgt_path) {
-            warn = true;
-            "!delete"
+    let tgt_text = read_text(tgt_path, conv)?;
+    if block::get(&tgt_text).is_none() {
+        return Ok(false);
+    }
+    let new_text = block::remove(&tgt_text);
+
+    let (kind, reason) = if ignore.is_some() {
+        (Kind::Ignore, Reason::Ignored)
+    } else {
+        (Kind::Unblock, Reason::Skeleton)
+    };
+    let status = LocalStatus::new(&ctx.tgt.status_file(path), true);
+    let action = Action::new(path, kind, reason, ignore, status, Strategy::Block);
+
+    if !dry_run && action.is_change() {
+        if new_text.trim().is_empty() {
+            remove_recursive(tgt_path)?;
         } else {
-            "missing"
-        };
-        println!("  {}: {}", indicator, path.to_string_lossy());
-    } else if !ignored && path_exists(&tgt_path) {
-        remove_recursive(&tgt_path)?;
+            fs::write(tgt_path, conv.apply(new_text.as_bytes()))?;
+        }
+    }
+    ctx.report.action(action);
+
+    Ok(false)
+}
+
+fn ignore_source(ctx: &Context, path: &Path) -> Option<IgnoreSource> {
+    if is_ignore(&ctx.src_ignore, path) {
+        Some(IgnoreSource::Skeleton)
+    } else if is_ignore(&ctx.user_ignore, path) {
+        Some(IgnoreSource::User)
+    } else if is_ignore(&ctx.tgt_ignore, path) {
+        Some(IgnoreSource::Project)
+    } else {
+        None
     }
+}
+
+fn get_ignore(repo: &Repository, lines: &[String]) -> Result<Gitignore, Error> {
+    let root = PathBuf::from(repo.workdir().unwrap());
+    let path = root.join(".gitskelignore");
+    let mut builder = GitignoreBuilder::new(root);
+    builder.add(path);
+    for line in lines {
+        builder.add_line(None, line)?;
+    }
+    builder.add_line(None, ".gitskelignore")?;
+    builder.add_line(None, MANIFEST_FILE)?;
+    Ok(builder.build()?)
+}
 
-    Ok(warn)
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|x| x.file_type().is_symlink())
+        .unwrap_or(false)
 }
 
 fn path_exists(path: &Path) -> bool {
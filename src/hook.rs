@@ -0,0 +1,175 @@
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::manifest::Hooks;
+use crate::report::Report;
+use console::Term;
+use failure::{bail, Error, ResultExt};
+use git2::Repository;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookMode {
+    /// Asks before running hooks which are not trusted yet
+    Prompt,
+    /// Trusts all hooks without asking
+    Trust,
+    /// Never runs hooks
+    Skip,
+}
+
+impl HookMode {
+    pub fn new(trust_hooks: bool, no_hooks: bool) -> Self {
+        if no_hooks {
+            HookMode::Skip
+        } else if trust_hooks {
+            HookMode::Trust
+        } else {
+            HookMode::Prompt
+        }
+    }
+}
+
+/// Checks whether hooks of skeleton repository can be run.
+///
+/// Hooks come from a remote repository, so commands which are not in `trusted_hooks` of config are shown,
+/// and the user is asked to trust them. Trusted commands are recorded to config.
+pub fn confirm(config: &mut Config, hooks: &Hooks, mode: HookMode) -> Result<bool, Error> {
+    if hooks.is_empty() || mode == HookMode::Skip {
+        return Ok(false);
+    }
+
+    let untrusted: Vec<String> = hooks
+        .commands()
+        .filter(|x| !config.trusted_hooks.contains(x))
+        .cloned()
+        .collect();
+
+    if untrusted.is_empty() {
+        return Ok(true);
+    }
+
+    if mode == HookMode::Prompt {
+        let term = Term::stderr();
+        if !term.is_term() {
+            return Err(ErrorKind::HookNotTrusted.into());
+        }
+
+        term.write_line("Skeleton hooks")?;
+        for x in &untrusted {
+            term.write_line(&format!("  untrust: {}", x))?;
+        }
+        term.write_str("Run these hooks? [y/N] ")?;
+        let answer = term.read_line()?;
+        if !["y", "yes"].contains(&answer.trim().to_lowercase().as_str()) {
+            return Err(ErrorKind::HookNotTrusted.into());
+        }
+    }
+
+    config.trust_hooks(untrusted);
+    Ok(true)
+}
+
+/// Runs hook commands at the root of target repository.
+///
+/// The following environment variables are passed to the commands:
+///
+/// - `GIT_SKEL_URL`         : URL of skeleton repository
+/// - `GIT_SKEL_OLD_REVISION`: applied revision before this command ( empty at init )
+/// - `GIT_SKEL_NEW_REVISION`: revision to be applied
+///
+/// With `--format json` / `--format ndjson`, the output of the commands is redirected to stderr,
+/// so that stdout carries only the report.
+pub fn run(
+    tgt: &Repository,
+    commands: &[String],
+    url: &str,
+    old_revision: Option<&str>,
+    new_revision: &str,
+    report: &Report,
+) -> Result<(), Error> {
+    let tgt_root = PathBuf::from(tgt.workdir().unwrap());
+
+    for command in commands {
+        report.hook(command);
+        let mut child = shell(command);
+        if !report.is_human() {
+            child.stdout(Stdio::from(io::stderr()));
+        }
+        let status = child
+            .current_dir(&tgt_root)
+            .env("GIT_SKEL_URL", url)
+            .env("GIT_SKEL_OLD_REVISION", old_revision.unwrap_or(""))
+            .env("GIT_SKEL_NEW_REVISION", new_revision)
+            .status()
+            .context(ErrorKind::HookFailed(command.clone()))?;
+
+        if !status.success() {
+            bail!(ErrorKind::HookFailed(command.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn shell(command: &str) -> Command {
+    let mut ret = Command::new("cmd");
+    ret.arg("/C").arg(command);
+    ret
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell(command: &str) -> Command {
+    let mut ret = Command::new("sh");
+    ret.arg("-c").arg(command);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hooks(command: &str) -> Hooks {
+        Hooks {
+            pre_apply: Vec::new(),
+            post_apply: vec![String::from(command)],
+        }
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(HookMode::new(false, false), HookMode::Prompt);
+        assert_eq!(HookMode::new(true, false), HookMode::Trust);
+        assert_eq!(HookMode::new(false, true), HookMode::Skip);
+        assert_eq!(HookMode::new(true, true), HookMode::Skip);
+    }
+
+    #[test]
+    fn test_confirm() {
+        let mut config = Config::new("https://example.com/skel.git", None, None, "");
+
+        // No hooks are run without hooks or with `--no-hooks`
+        assert!(!confirm(&mut config, &Hooks::default(), HookMode::Trust).unwrap());
+        assert!(!confirm(&mut config, &hooks("make"), HookMode::Skip).unwrap());
+        assert!(config.trusted_hooks.is_empty());
+
+        // `--trust-hooks` records the commands
+        assert!(confirm(&mut config, &hooks("make"), HookMode::Trust).unwrap());
+        assert_eq!(config.trusted_hooks, vec![String::from("make")]);
+
+        // Trusted commands are run without asking
+        assert!(confirm(&mut config, &hooks("make"), HookMode::Prompt).unwrap());
+
+        // Changed commands are asked again, and are not trusted without terminal
+        if !Term::stderr().is_term() {
+            let err = confirm(&mut config, &hooks("make install"), HookMode::Prompt).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<ErrorKind>(),
+                Some(ErrorKind::HookNotTrusted)
+            ));
+            assert_eq!(config.trusted_hooks, vec![String::from("make")]);
+        }
+    }
+}
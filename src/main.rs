@@ -1,16 +1,49 @@
-use crate::config::Config;
+use crate::auth::Auth;
+use crate::batch::{Batch, Outcome};
+use crate::config::{Config, Layer};
 use crate::error::ErrorKind;
+use crate::file::Context;
+use crate::hook::HookMode;
+use crate::manifest::Manifest;
+use crate::report::{OutputFormat, Phase, Report};
+use crate::source::{Origin, Source};
+use crate::template::Templates;
+use crate::user::UserConfig;
 use console::Term;
 use failure::{bail, Error, ResultExt};
-use git2::{BranchType, Delta, ObjectType, Oid, Repository};
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use std::path::PathBuf;
+use git2::build::RepoBuilder;
+use git2::{BranchType, Commit, Delta, FetchOptions, ObjectType, Repository};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use structopt::{clap, StructOpt};
 use tempfile::TempDir;
-
+use toml::Table;
+
+mod archive;
+mod auth;
+mod batch;
+mod block;
+mod bundle;
+mod component;
+mod condition;
 mod config;
+mod eol;
 mod error;
 mod file;
+mod hook;
+mod manifest;
+mod patch;
+mod remote;
+mod report;
+mod source;
+mod strategy;
+mod structured;
+mod tag;
+mod template;
+mod user;
+mod variable;
 
 // ---------------------------------------------------------------------------------------------------------------------
 // Opt
@@ -20,7 +53,16 @@ mod file;
 #[structopt(long_version = option_env!("LONG_VERSION").unwrap_or(env!("CARGO_PKG_VERSION")))]
 #[structopt(setting = clap::AppSettings::ColoredHelp)]
 #[structopt(setting = clap::AppSettings::DeriveDisplayOrder)]
-pub enum Opt {
+pub struct Opt {
+    /// Output format [human|json|ndjson]
+    #[structopt(long = "format", global = true, default_value = "human")]
+    format: OutputFormat,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
     #[structopt(name = "init", about = "Initializes")]
     #[structopt(setting = clap::AppSettings::ColoredHelp)]
     Init {
@@ -32,6 +74,17 @@ pub enum Opt {
         tag: Option<String>,
         #[structopt(short = "f", long = "force")]
         force: bool,
+        #[structopt(long = "trust-hooks")]
+        trust_hooks: bool,
+        #[structopt(long = "no-hooks")]
+        no_hooks: bool,
+        #[structopt(long = "var", name = "KEY=VALUE", number_of_values = 1)]
+        vars: Vec<String>,
+        #[structopt(long = "vars-file", parse(from_os_str))]
+        vars_file: Option<PathBuf>,
+        /// Applies uncommitted changes of a local skeleton
+        #[structopt(long = "dirty")]
+        dirty: bool,
     },
     #[structopt(
         name = "update",
@@ -41,6 +94,13 @@ pub enum Opt {
     Update {
         #[structopt(short = "f", long = "force")]
         force: bool,
+        #[structopt(long = "trust-hooks")]
+        trust_hooks: bool,
+        #[structopt(long = "no-hooks")]
+        no_hooks: bool,
+        /// Applies uncommitted changes of a local skeleton
+        #[structopt(long = "dirty")]
+        dirty: bool,
     },
     #[structopt(name = "branch", about = "Sets tracking branck")]
     #[structopt(setting = clap::AppSettings::ColoredHelp)]
@@ -49,6 +109,10 @@ pub enum Opt {
         branch: String,
         #[structopt(short = "f", long = "force")]
         force: bool,
+        #[structopt(long = "trust-hooks")]
+        trust_hooks: bool,
+        #[structopt(long = "no-hooks")]
+        no_hooks: bool,
     },
     #[structopt(name = "tag", about = "Sets tracking tag")]
     #[structopt(setting = clap::AppSettings::ColoredHelp)]
@@ -57,6 +121,69 @@ pub enum Opt {
         tag: String,
         #[structopt(short = "f", long = "force")]
         force: bool,
+        #[structopt(long = "trust-hooks")]
+        trust_hooks: bool,
+        #[structopt(long = "no-hooks")]
+        no_hooks: bool,
+    },
+    #[structopt(
+        name = "status",
+        about = "Shows the applied and the latest revision of the upstream repository"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Status,
+    #[structopt(
+        name = "check",
+        about = "Checks whether an update is available or files are modified without writing"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Check,
+    #[structopt(
+        name = "export-patch",
+        about = "Exports local changes of skeleton files as a patch series for the skeleton repository"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    ExportPatch {
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    #[structopt(name = "batch", about = "Updates repositories listed in the manifest")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Batch {
+        #[structopt(name = "MANIFEST", parse(from_os_str))]
+        manifest: PathBuf,
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+        #[structopt(long = "trust-hooks")]
+        trust_hooks: bool,
+        #[structopt(long = "no-hooks")]
+        no_hooks: bool,
+    },
+    #[structopt(name = "vars", about = "Changes the answers of skeleton variables")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Vars {
+        #[structopt(subcommand)]
+        command: VarsCommand,
+    },
+    #[structopt(name = "component", about = "Enables or disables skeleton components")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Component {
+        #[structopt(subcommand)]
+        command: ComponentCommand,
+    },
+    #[structopt(
+        name = "config",
+        about = "Shows all values of config, or validates or edits .gitskel.toml"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Config {
+        #[structopt(
+            long = "show-origin",
+            help = "Shows the file where each value comes from"
+        )]
+        show_origin: bool,
+        #[structopt(subcommand)]
+        command: Option<ConfigCommand>,
     },
     #[structopt(name = "clean", about = "Removes skeleton files")]
     #[structopt(setting = clap::AppSettings::ColoredHelp)]
@@ -66,181 +193,783 @@ pub enum Opt {
     },
 }
 
+#[derive(Debug, StructOpt)]
+pub enum VarsCommand {
+    #[structopt(
+        name = "set",
+        about = "Sets the answers and re-renders the template files"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Set {
+        #[structopt(name = "KEY=VALUE", required = true)]
+        vars: Vec<String>,
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ComponentCommand {
+    #[structopt(name = "enable", about = "Enables the component and applies its files")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Enable {
+        #[structopt(name = "NAME")]
+        name: String,
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+    },
+    #[structopt(
+        name = "disable",
+        about = "Disables the component and removes its files"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Disable {
+        #[structopt(name = "NAME")]
+        name: String,
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigCommand {
+    #[structopt(
+        name = "validate",
+        about = "Checks the url, the branch or tag, the revision and the templates"
+    )]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Validate,
+    #[structopt(name = "get", about = "Shows the value of the key")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Get {
+        #[structopt(name = "KEY")]
+        key: String,
+        #[structopt(
+            long = "show-origin",
+            help = "Shows the file where the value comes from"
+        )]
+        show_origin: bool,
+    },
+    #[structopt(name = "set", about = "Sets the value of the key")]
+    #[structopt(setting = clap::AppSettings::ColoredHelp)]
+    Set {
+        #[structopt(name = "KEY")]
+        key: String,
+        #[structopt(name = "VALUE")]
+        value: String,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Init { .. } => "init",
+            Command::Update { .. } => "update",
+            Command::Branch { .. } => "branch",
+            Command::Tag { .. } => "tag",
+            Command::Status => "status",
+            Command::Check => "check",
+            Command::ExportPatch { .. } => "export-patch",
+            Command::Batch { .. } => "batch",
+            Command::Vars { .. } => "vars",
+            Command::Component { .. } => "component",
+            Command::Config { .. } => "config",
+            Command::Clean { .. } => "clean",
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------------------------------------------------
 // Subcommands
 // ---------------------------------------------------------------------------------------------------------------------
 
-fn cmd_init(url: &str, branch: Option<&str>, tag: Option<&str>, force: bool) -> Result<(), Error> {
+#[allow(clippy::too_many_arguments)]
+fn cmd_init(
+    url: &str,
+    branch: Option<&str>,
+    tag: Option<&str>,
+    force: bool,
+    hook_mode: HookMode,
+    changes: &Changes,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
     let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
 
     Config::check(&tgt)?;
 
-    let (src, _dir) =
-        setup_src(url, None, branch, tag).context(ErrorKind::RepoClone(String::from(url)))?;
-    let commit = src.head()?.peel_to_commit()?;
-    let config = Config::new(url, branch, tag, &commit);
-
-    let src_ignore = get_ignore(&src)?;
-    let tgt_ignore = get_ignore(&tgt)?;
-
-    println!("Detect changes");
-    init(&src, &tgt, &src_ignore, &tgt_ignore, force, true)?;
-    println!("Apply changes");
-    init(&src, &tgt, &src_ignore, &tgt_ignore, force, false)?;
+    // The canonical URL is recorded instead of the alias, the shorthand or the relative path
+    let url = source::canonical_url(&user.expand(url));
+    let url = url.as_str();
+
+    // The fallback without the preferred branch reuses the fetched repository
+    let mut fetcher = Fetcher::shared(&tgt)?;
+
+    // The preferred branch of the user config is tracked only if it exists
+    let preferred = match (branch, tag, &user.branch) {
+        (None, None, Some(x)) => {
+            match setup_src(
+                &mut fetcher,
+                url,
+                None,
+                Some(x.as_str()),
+                None,
+                &[],
+                changes.dirty,
+            ) {
+                Ok(src) => Some((src, x.as_str())),
+                Err(x) if is_branch_not_found(&x) => None,
+                Err(x) => return Err(x.context(ErrorKind::RepoClone(String::from(url))).into()),
+            }
+        }
+        _ => None,
+    };
+    let (src, branch) = match preferred {
+        Some((src, x)) => (src, Some(x)),
+        None => (
+            setup_src(&mut fetcher, url, None, branch, tag, &[], changes.dirty)
+                .context(ErrorKind::RepoClone(String::from(url)))?,
+            branch,
+        ),
+    };
+    let mut config = Config::new(url, branch, tag, &src.revision);
+    config.set_layers(&src.layers);
+    config.set_applied_tag(tag::applied(&src, tag)?);
+
+    let manifest = Manifest::load(&src)?;
+    variable::resolve(&manifest.variables, &mut config.vars, &changes.vars, true)?;
+    component::resolve(&manifest.components, &mut config.components);
+    let ctx = Context::new(&src, &tgt, &manifest, &config, user, report)?;
+    ctx.templates
+        .render_tree(&src, &config.vars, &config.vars)?;
+
+    report.revisions(None, &config.revision);
+    report.phase(Phase::Detect);
+    init(&ctx, force, true)?;
+    let run_hooks = hook::confirm(&mut config, &manifest.hooks, hook_mode)?;
+    if run_hooks {
+        hook::run(
+            &tgt,
+            &manifest.hooks.pre_apply,
+            url,
+            None,
+            &config.revision,
+            report,
+        )?;
+    }
+    report.phase(Phase::Apply);
+    init(&ctx, force, false)?;
+    if run_hooks {
+        hook::run(
+            &tgt,
+            &manifest.hooks.post_apply,
+            url,
+            None,
+            &config.revision,
+            report,
+        )?;
+    }
 
     config.save(&tgt)?;
+    warn_dirty(&src, report);
 
     Ok(())
 }
 
-fn cmd_update(force: bool) -> Result<(), Error> {
+fn cmd_update(
+    force: bool,
+    hook_mode: HookMode,
+    changes: &Changes,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
     let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
     let mut config = Config::load(&tgt)?;
 
-    let (src, _dir) = setup_src(
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
         &config.url,
         None,
         config.branch.as_ref(),
         config.tag.as_ref(),
+        &config.layers,
+        changes.dirty,
     )
     .context(ErrorKind::RepoClone(config.url.clone()))?;
 
-    let src_ignore = get_ignore(&src)?;
-    let tgt_ignore = get_ignore(&tgt)?;
-
-    println!("Detect changes");
-    update(
-        &mut config,
-        &src,
+    apply_update(
         &tgt,
-        &src_ignore,
-        &tgt_ignore,
-        force,
-        true,
-    )?;
-    println!("Apply changes");
-    update(
         &mut config,
         &src,
-        &tgt,
-        &src_ignore,
-        &tgt_ignore,
         force,
-        false,
-    )?;
+        hook_mode,
+        changes,
+        user,
+        report,
+    )
+}
 
-    let commit = src.head()?.peel_to_commit()?;
-    config.set_commit(&commit);
+fn cmd_branch(
+    branch: &str,
+    force: bool,
+    hook_mode: HookMode,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    let tgt = Repository::discover(".")?;
+    let mut config = Config::load(&tgt)?;
+    config.set_branch(branch);
 
-    config.save(&tgt)?;
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        None,
+        config.branch.as_ref(),
+        config.tag.as_ref(),
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
 
-    Ok(())
+    apply_update(
+        &tgt,
+        &mut config,
+        &src,
+        force,
+        hook_mode,
+        &Changes::default(),
+        user,
+        report,
+    )
 }
 
-fn cmd_branch(branch: &str, force: bool) -> Result<(), Error> {
+fn cmd_tag(
+    tag: &str,
+    force: bool,
+    hook_mode: HookMode,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
     let tgt = Repository::discover(".")?;
     let mut config = Config::load(&tgt)?;
-    config.set_branch(&branch);
+    config.set_tag(tag);
 
-    let (src, _dir) = setup_src(
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
         &config.url,
         None,
         config.branch.as_ref(),
         config.tag.as_ref(),
+        &config.layers,
+        false,
     )
     .context(ErrorKind::RepoClone(config.url.clone()))?;
 
-    let src_ignore = get_ignore(&src)?;
-    let tgt_ignore = get_ignore(&tgt)?;
-
-    println!("Detect changes");
-    update(
-        &mut config,
-        &src,
+    apply_update(
         &tgt,
-        &src_ignore,
-        &tgt_ignore,
-        force,
-        true,
-    )?;
-    println!("Apply changes");
-    update(
         &mut config,
         &src,
-        &tgt,
-        &src_ignore,
-        &tgt_ignore,
         force,
-        false,
-    )?;
+        hook_mode,
+        &Changes::default(),
+        user,
+        report,
+    )
+}
 
-    let commit = src.head()?.peel_to_commit()?;
-    config.set_commit(&commit);
+fn cmd_status(report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let config = Config::load(&tgt)?;
 
-    config.save(&tgt)?;
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        None,
+        config.branch.as_ref(),
+        config.tag.as_ref(),
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
+
+    let latest_revision = src.revision.clone();
+    let latest_tag = tag::applied(&src, config.tag.as_ref())?;
+    let parents = src
+        .layers
+        .iter()
+        .map(|layer| report::Parent {
+            url: layer.url.clone(),
+            applied_revision: config
+                .layers
+                .iter()
+                .find(|x| x.url == layer.url)
+                .map(|x| x.revision.clone()),
+            latest_revision: layer.revision.clone(),
+        })
+        .collect();
+
+    report.status(report::Status {
+        up_to_date: src.is_applied(&config),
+        url: config.url,
+        branch: config.branch,
+        tag: config.tag,
+        applied_revision: config.revision,
+        applied_tag: config.applied_tag,
+        latest_revision,
+        latest_tag,
+        parents,
+    });
 
     Ok(())
 }
 
-fn cmd_tag(tag: &str, force: bool) -> Result<(), Error> {
-    let tgt = Repository::discover(".")?;
+fn cmd_check(user: &UserConfig, report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
     let mut config = Config::load(&tgt)?;
-    config.set_tag(&tag);
 
-    let (src, _dir) = setup_src(
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
         &config.url,
         None,
         config.branch.as_ref(),
         config.tag.as_ref(),
+        &config.layers,
+        false,
     )
     .context(ErrorKind::RepoClone(config.url.clone()))?;
+    let latest_revision = src.revision.clone();
+    let up_to_date = src.is_applied(&config);
+
+    // Compare project files with the applied revision, so that only local changes are detected
+    let commit = source::compose(&src, &config.revision, &config.layers)?;
+    source::checkout(&src, &commit)?;
+
+    let manifest = Manifest::load(&src)?;
+    // The resolved answers are used only to render templates, and are never saved.
+    // The check never prompts, so a variable without any value is an error.
+    variable::resolve(&manifest.variables, &mut config.vars, &Table::new(), false)?;
+    component::resolve(&manifest.components, &mut config.components);
+    let mut ctx = Context::new(&src, &tgt, &manifest, &config, user, report)?;
+    ctx.base = Some(commit.tree()?);
+    ctx.templates
+        .render_tree(&src, &config.vars, &config.vars)?;
+
+    report.revisions(Some(&config.revision), &latest_revision);
+    report.phase(Phase::Detect);
+    update(&ctx, true, true)?;
+
+    let drift: Vec<_> = report
+        .planned()
+        .into_iter()
+        .filter(|x| x.is_drift())
+        .map(|x| x.path)
+        .collect();
+    let result = if !drift.is_empty() {
+        "local-drift"
+    } else if !up_to_date {
+        "update-available"
+    } else {
+        "up-to-date"
+    };
+
+    let drift_count = drift.len();
+    report.check(report::Check {
+        result,
+        applied_revision: config.revision.clone(),
+        latest_revision,
+        up_to_date,
+        drift,
+    });
+
+    if drift_count != 0 {
+        return Err(ErrorKind::CheckDrift(drift_count).into());
+    }
+    if !up_to_date {
+        return Err(ErrorKind::CheckUpdateAvailable.into());
+    }
+
+    Ok(())
+}
+
+fn cmd_export_patch(
+    output: Option<&Path>,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    // The report of machine readable formats is written to stdout
+    if output.is_none() && !report.is_human() {
+        return Err(ErrorKind::PatchOutputRequired.into());
+    }
+
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let config = Config::load(&tgt)?;
+
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        Some(&config.revision),
+        None,
+        None,
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
+
+    let manifest = Manifest::load(&src)?;
+    let ctx = Context::new(&src, &tgt, &manifest, &config, user, report)?;
+
+    let mut files = Vec::new();
+    for index in src.index()?.iter() {
+        let path = PathBuf::from(&String::from_utf8(index.path)?);
+        if !ctx.is_included(&path) {
+            continue;
+        }
+        if let Some(content) = file::export(&ctx, &path)? {
+            report.patch(&path.to_string_lossy());
+            files.push((path, content));
+        }
+    }
+
+    // Patches are applied to the skeleton itself, even if it extends parent skeletons
+    let base = src.commit()?;
+    let series = patch::series(&src, &base, &files, &tgt.signature()?)?;
+
+    if let Some(output) = output {
+        fs::write(output, series)?;
+    } else {
+        std::io::stdout().write_all(&series)?;
+    }
 
-    let src_ignore = get_ignore(&src)?;
-    let tgt_ignore = get_ignore(&tgt)?;
+    Ok(())
+}
+
+fn cmd_batch(
+    manifest: &Path,
+    force: bool,
+    hook_mode: HookMode,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    let batch = Batch::load(manifest)?;
+
+    let mut fetcher: Option<Fetcher> = None;
+    let mut outcomes = Vec::new();
+
+    for entry in &batch.repositories {
+        let path = entry.path.to_string_lossy();
+        report.repository(&path);
+
+        let result = (|| -> Result<Outcome, Error> {
+            if let Some(ref url) = entry.clone {
+                if !entry.path.exists() {
+                    let config = git2::Config::open_default()?;
+                    clone_repo(&config, url, &entry.path)
+                        .context(ErrorKind::RepoClone(url.clone()))?;
+                }
+            }
+
+            // `discover` would update the enclosing repository if the path is not a repository root
+            let tgt =
+                Repository::open(&entry.path).context(ErrorKind::RepoOpen(path.to_string()))?;
+            let mut config = Config::load(&tgt)?;
+
+            // Each URL is fetched once, and the branch or tag is resolved for each repository
+            let fetcher = match fetcher {
+                Some(ref mut x) => {
+                    x.config = tgt.config()?.snapshot()?;
+                    x
+                }
+                None => fetcher.insert(Fetcher::shared(&tgt)?),
+            };
+            let src = setup_src(
+                fetcher,
+                &config.url,
+                None,
+                config.branch.as_ref(),
+                config.tag.as_ref(),
+                &config.layers,
+                false,
+            )
+            .context(ErrorKind::RepoClone(config.url.clone()));
+            let src = match src {
+                Ok(src) => src,
+                Err(x) => return Ok(Outcome::Error(format!("{}", Error::from(x)))),
+            };
+
+            let old_revision = config.revision.clone();
+            let old_layers = config.layers.clone();
+            apply_update(
+                &tgt,
+                &mut config,
+                &src,
+                force,
+                hook_mode,
+                &Changes::default(),
+                user,
+                report,
+            )?;
+
+            if old_revision == config.revision && old_layers == config.layers {
+                Ok(Outcome::UpToDate)
+            } else {
+                Ok(Outcome::Updated)
+            }
+        })();
+
+        let outcome = match result {
+            Ok(x) => x,
+            Err(x) => Outcome::from_error(&x),
+        };
+        report.outcome(&path, &outcome);
+        outcomes.push(outcome);
+    }
+
+    batch::finish(&outcomes)
+}
 
-    println!("Detect changes");
-    update(
+fn cmd_vars_set(
+    given: &Table,
+    force: bool,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let mut config = Config::load(&tgt)?;
+
+    // Keep the applied revision, and re-render only
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        Some(&config.revision),
+        None,
+        None,
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
+
+    let changes = Changes {
+        vars: given.clone(),
+        scoped: true,
+        ..Default::default()
+    };
+    apply_update(
+        &tgt,
         &mut config,
         &src,
-        &tgt,
-        &src_ignore,
-        &tgt_ignore,
         force,
-        true,
-    )?;
-    println!("Apply changes");
-    update(
+        HookMode::Skip,
+        &changes,
+        user,
+        report,
+    )
+}
+
+fn cmd_component(
+    name: &str,
+    enable: bool,
+    force: bool,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let mut config = Config::load(&tgt)?;
+
+    // Keep the applied revision, and apply or remove the files of the component only
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        Some(&config.revision),
+        None,
+        None,
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
+
+    let changes = Changes {
+        components: vec![(String::from(name), enable)],
+        scoped: true,
+        ..Default::default()
+    };
+    apply_update(
+        &tgt,
         &mut config,
         &src,
-        &tgt,
-        &src_ignore,
-        &tgt_ignore,
         force,
+        HookMode::Skip,
+        &changes,
+        user,
+        report,
+    )
+}
+
+fn cmd_config_validate(report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let config = Config::load(&tgt)?;
+    let tgt_config = tgt.config()?.snapshot()?;
+
+    let mut errors = 0;
+    let mut validate = |item, result: Result<(), Error>| {
+        if result.is_err() {
+            errors += 1;
+        }
+        report.validation(item, result);
+    };
+
+    validate(
+        "url",
+        remote::rewrite_url(&tgt_config, &config.url).and_then(|x| remote::check_url(&x)),
+    );
+
+    let item = if config.branch.is_some() {
+        "branch"
+    } else if config.tag.is_some() {
+        "tag"
+    } else {
+        "source"
+    };
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        None,
+        config.branch.as_ref(),
+        config.tag.as_ref(),
+        &config.layers,
         false,
-    )?;
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()));
+    let src = match src {
+        Ok(x) => {
+            validate(item, Ok(()));
+            x
+        }
+        Err(x) => {
+            validate(item, Err(x.into()));
+            return Err(ErrorKind::ConfigInvalid(errors).into());
+        }
+    };
+
+    validate("revision", validate_revision(&src, &config));
+    validate("templates", validate_templates(&src, &config));
+
+    if errors != 0 {
+        return Err(ErrorKind::ConfigInvalid(errors).into());
+    }
+
+    Ok(())
+}
 
-    let commit = src.head()?.peel_to_commit()?;
-    config.set_commit(&commit);
+fn cmd_config_list(show_origin: bool, report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let config = Config::load(&tgt)?;
+
+    for (key, value) in config.entries()? {
+        let origin = show_origin.then(|| config.origin(&key));
+        report.entry(&key, value, origin);
+    }
+
+    Ok(())
+}
 
+fn cmd_config_get(key: &str, show_origin: bool, report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let config = Config::load(&tgt)?;
+
+    match config.get(key)? {
+        Some(x) if show_origin => report.entry(key, x, Some(config.origin(key))),
+        Some(x) => report.value(key, x),
+        None => bail!(ErrorKind::ConfigKeyNotSet(String::from(key))),
+    }
+
+    Ok(())
+}
+
+fn cmd_config_set(key: &str, value: &str, report: &Report) -> Result<(), Error> {
+    let tgt = Repository::discover(".").context(ErrorKind::RepoDiscover)?;
+    let mut config = Config::load(&tgt)?;
+
+    let value = match key.split_once('.') {
+        None if key == "url" => {
+            let value = source::canonical_url(&UserConfig::load()?.expand(value));
+            let tgt_config = tgt.config()?.snapshot()?;
+            remote::check_url(&remote::rewrite_url(&tgt_config, &value)?)?;
+            value
+        }
+        Some(("vars", name)) => {
+            // The answer is checked by the declaration of the applied revision, because update never checks it again
+            let manifest = applied_manifest(&tgt, &config)?;
+            let var = match manifest.variables.iter().find(|x| x.name == name) {
+                Some(x) => x,
+                None => bail!(ErrorKind::VariableUnknown(String::from(name))),
+            };
+            let value = var
+                .coerce(&toml::Value::from(value))
+                .context(ErrorKind::VariableInvalid(String::from(name)))?;
+            // The coerced value is given as TOML to keep its type
+            value.to_string()
+        }
+        Some(("components", name)) => {
+            let manifest = applied_manifest(&tgt, &config)?;
+            if !manifest.components.iter().any(|x| x.name == name) {
+                bail!(ErrorKind::ComponentNotFound(String::from(name)));
+            }
+            String::from(value)
+        }
+        _ => String::from(value),
+    };
+    config.set(key, &value)?;
     config.save(&tgt)?;
 
+    let origin = config.origin(key);
+    if origin != config::ORIGIN_CONFIG && report.is_human() {
+        eprintln!(
+            "warning: `{}` is overridden by {}, so the saved value is not used in this repository",
+            key, origin
+        );
+    }
+
     Ok(())
 }
 
-fn cmd_clean(force: bool) -> Result<(), Error> {
+/// Loads the skeleton manifest at the applied revision.
+fn applied_manifest(tgt: &Repository, config: &Config) -> Result<Manifest, Error> {
+    let src = setup_src(
+        &mut Fetcher::new(tgt)?,
+        &config.url,
+        Some(&config.revision),
+        None,
+        None,
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
+    Manifest::load(&src)
+}
+
+fn cmd_clean(force: bool, user: &UserConfig, report: &Report) -> Result<(), Error> {
     let tgt = Repository::discover(".")?;
     let config = Config::load(&tgt)?;
 
-    let (src, _dir) = setup_src(&config.url, Some(&config.revision), None, None)
-        .context(ErrorKind::RepoClone(config.url.clone()))?;
+    let src = setup_src(
+        &mut Fetcher::new(&tgt)?,
+        &config.url,
+        Some(&config.revision),
+        None,
+        None,
+        &config.layers,
+        false,
+    )
+    .context(ErrorKind::RepoClone(config.url.clone()))?;
 
-    let src_ignore = get_ignore(&src)?;
-    let tgt_ignore = get_ignore(&tgt)?;
+    let manifest = Manifest::load(&src)?;
+    let ctx = Context::new(&src, &tgt, &manifest, &config, user, report)?;
 
-    println!("Detect changes");
-    clean(&src, &tgt, &src_ignore, &tgt_ignore, force, true)?;
-    println!("Apply changes");
-    clean(&src, &tgt, &src_ignore, &tgt_ignore, force, false)?;
+    report.revisions(Some(&config.revision), &config.revision);
+    report.phase(Phase::Detect);
+    clean(&ctx, force, true)?;
+    report.phase(Phase::Apply);
+    clean(&ctx, force, false)?;
 
     Config::delete(&tgt)?;
 
@@ -251,61 +980,390 @@ fn cmd_clean(force: bool) -> Result<(), Error> {
 // Support functions
 // ---------------------------------------------------------------------------------------------------------------------
 
+/// Changes of variables and components requested by the command
+#[derive(Default)]
+struct Changes {
+    vars: Table,
+    components: Vec<(String, bool)>,
+    /// Whether uncommitted changes of a local skeleton are applied
+    dirty: bool,
+    /// Whether only the files affected by `vars` and `components` are applied
+    scoped: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_update(
+    tgt: &Repository,
+    config: &mut Config,
+    src: &Source,
+    force: bool,
+    hook_mode: HookMode,
+    changes: &Changes,
+    user: &UserConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    let manifest = Manifest::load(src)?;
+    let old_vars = config.vars.clone();
+    variable::resolve(&manifest.variables, &mut config.vars, &changes.vars, true)?;
+
+    for name in changes.vars.keys() {
+        config.mark_changed(&format!("vars.{}", name));
+    }
+    let old_enabled = config.components.clone();
+    component::resolve(&manifest.components, &mut config.components);
+
+    let mut ctx = Context::new(src, tgt, &manifest, config, user, report)?;
+    for (name, enable) in &changes.components {
+        if !ctx.components.contains(name) {
+            bail!(ErrorKind::ComponentNotFound(name.clone()));
+        }
+        ctx.enabled.insert(name.clone(), *enable);
+        config.components.insert(name.clone(), *enable);
+        config.mark_changed(&format!("components.{}", name));
+    }
+    ctx.rendered = ctx.templates.render_tree(src, &config.vars, &old_vars)?;
+    ctx.old_vars = old_vars;
+    ctx.old_enabled = old_enabled;
+    ctx.base = Some(source::compose(src, &config.revision, &config.layers)?.tree()?);
+
+    // Modified files unrelated to the changes are neither aborted nor overwritten
+    if changes.scoped {
+        let mut scope = ctx.rendered.clone();
+        for index in src.index()?.iter() {
+            let path = PathBuf::from(&String::from_utf8(index.path)?);
+            let toggled = ctx.conditions.is_included(&path, &ctx.vars)
+                != ctx.conditions.is_included(&path, &ctx.old_vars)
+                || changes
+                    .components
+                    .iter()
+                    .any(|(name, _)| ctx.components.is_match(name, &path));
+            if toggled && !scope.contains(&path) {
+                scope.push(path);
+            }
+        }
+        ctx.scope = Some(scope);
+    }
+
+    let old_revision = config.revision.clone();
+    let new_revision = src.revision.clone();
+
+    report.revisions(Some(&old_revision), &new_revision);
+    report.phase(Phase::Detect);
+    update(&ctx, force, true)?;
+    // `no_hooks` of config is a default, which `--trust-hooks` can override
+    let hook_mode = match hook_mode {
+        HookMode::Prompt if config.no_hooks => HookMode::Skip,
+        x => x,
+    };
+    let run_hooks = hook::confirm(config, &manifest.hooks, hook_mode)?;
+    if run_hooks {
+        hook::run(
+            tgt,
+            &manifest.hooks.pre_apply,
+            &config.url,
+            Some(&old_revision),
+            &new_revision,
+            report,
+        )?;
+    }
+    report.phase(Phase::Apply);
+    update(&ctx, force, false)?;
+    if run_hooks {
+        hook::run(
+            tgt,
+            &manifest.hooks.post_apply,
+            &config.url,
+            Some(&old_revision),
+            &new_revision,
+            report,
+        )?;
+    }
+
+    if !src.is_applied(config) {
+        config.set_revision(&src.revision);
+        config.set_layers(&src.layers);
+        config.set_applied_tag(tag::applied(src, config.tag.as_ref())?);
+    }
+
+    config.save(tgt)?;
+    warn_dirty(src, report);
+
+    Ok(())
+}
+
+/// Checks that the applied revisions are reachable from the skeleton repository.
+fn validate_revision(src: &Source, config: &Config) -> Result<(), Error> {
+    if config.revision.ends_with(source::DIRTY_SUFFIX) {
+        bail!("revision includes uncommitted changes: {}", config.revision);
+    }
+
+    let mut refs = Vec::new();
+    for reference in src.references()? {
+        if let Ok(x) = reference?.peel_to_commit() {
+            refs.push(x.id());
+        }
+    }
+    let revisions =
+        std::iter::once(&config.revision).chain(config.layers.iter().map(|x| &x.revision));
+    for revision in revisions {
+        let commit = source::find_revision(src, revision)
+            .context(ErrorKind::RevisionNotFound(revision.clone()))?;
+        let reachable = refs.iter().any(|x| {
+            *x == commit.id() || src.graph_descendant_of(*x, commit.id()).unwrap_or(false)
+        });
+        if !reachable {
+            bail!(
+                "revision is not reachable from any branch or tag: {}",
+                revision
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the templates of the latest revision are rendered with the recorded answers.
+fn validate_templates(src: &Source, config: &Config) -> Result<(), Error> {
+    let manifest = Manifest::load(src)?;
+    let mut vars = config.vars.clone();
+    for var in &manifest.variables {
+        match vars.get(&var.name) {
+            Some(x) => var
+                .check(x)
+                .context(ErrorKind::VariableInvalid(var.name.clone()))?,
+            None => match var.default {
+                Some(ref x) => {
+                    vars.insert(var.name.clone(), x.clone());
+                }
+                None => bail!(ErrorKind::VariableRequired(var.name.clone())),
+            },
+        }
+    }
+    let templates = Templates::new(&manifest.templates)?;
+    templates.render_tree(src, &vars, &vars)?;
+    Ok(())
+}
+
+/// Clones the skeleton and its parent skeletons.
+///
+/// If `revision` is given, the parent skeletons are checked out at the revisions recorded in `layers`.
 fn setup_src<T: AsRef<str>>(
+    fetcher: &mut Fetcher,
     url: T,
     revision: Option<T>,
     branch: Option<T>,
     tag: Option<T>,
-) -> Result<(Repository, TempDir), Error> {
+    layers: &[Layer],
+    dirty: bool,
+) -> Result<Source, Error> {
     let dir = tempfile::tempdir()?;
-    let src = Repository::clone(url.as_ref(), &dir)?;
-
-    {
-        let commit = if let Some(revision) = revision {
-            src.find_object(Oid::from_str(revision.as_ref())?, None)
-                .context(ErrorKind::RevisionNotFound(String::from(revision.as_ref())))?
-                .peel_to_commit()?
-        } else if let Some(branch) = branch {
-            src.find_branch(&format!("origin/{}", branch.as_ref()), BranchType::Remote)
-                .context(ErrorKind::BranchNotFound(String::from(branch.as_ref())))?
-                .get()
-                .peel_to_commit()?
-        } else if let Some(tag) = tag {
-            src.find_reference(&format!("refs/tags/{}", tag.as_ref()))
-                .context(ErrorKind::TagNotFound(String::from(tag.as_ref())))?
-                .peel_to_commit()?
-        } else {
-            src.head()?.peel_to_commit()?
+    let (src, origin) = fetcher.open(url.as_ref(), dir.path(), dirty)?;
+
+    let pinned = revision.is_some();
+    if origin != Origin::Dirty {
+        let commit = select_commit(&src, revision, branch, tag)?;
+        source::checkout(&src, &commit)?;
+    } else {
+        // The working tree is applied regardless of the tracking branch or tag
+        let commit = src.head()?.peel_to_commit()?;
+        source::checkout(&src, &commit)?;
+    }
+
+    let mut parent = Manifest::parent(&src)?;
+    let mut src = Source::new(src, dir, origin)?;
+    let mut urls = vec![String::from(url.as_ref())];
+
+    while let Some(x) = parent {
+        if urls.contains(&x.url) {
+            bail!(ErrorKind::ParentCircular(x.url));
+        }
+        urls.push(x.url.clone());
+
+        let revision = layers
+            .get(src.layers.len())
+            .filter(|layer| pinned && layer.url == x.url)
+            .map(|layer| layer.revision.as_str());
+
+        let dir = tempfile::tempdir()?;
+        let (repo, origin) = fetcher
+            .open(&x.url, dir.path(), false)
+            .context(ErrorKind::RepoClone(x.url.clone()))?;
+        {
+            let commit = select_commit(&repo, revision, x.branch.as_deref(), x.tag.as_deref())?;
+            source::checkout(&repo, &commit)?;
+        }
+
+        parent = Manifest::parent(&repo)?;
+        src.add_layer(&x.url, repo, dir, origin)?;
+    }
+
+    src.compose()?;
+
+    Ok(src)
+}
+
+fn select_commit<'a, T: AsRef<str>>(
+    src: &'a Repository,
+    revision: Option<T>,
+    branch: Option<T>,
+    tag: Option<T>,
+) -> Result<Commit<'a>, Error> {
+    let commit = if let Some(revision) = revision {
+        source::find_revision(src, revision.as_ref())
+            .context(ErrorKind::RevisionNotFound(String::from(revision.as_ref())))?
+    } else if let Some(branch) = branch {
+        src.find_branch(&format!("origin/{}", branch.as_ref()), BranchType::Remote)
+            .context(ErrorKind::BranchNotFound(String::from(branch.as_ref())))?
+            .get()
+            .peel_to_commit()?
+    } else if let Some(tag) = tag {
+        src.find_reference(&format!("refs/tags/{}", tag::find(src, tag.as_ref())?))
+            .context(ErrorKind::TagNotFound(String::from(tag.as_ref())))?
+            .peel_to_commit()?
+    } else {
+        src.head()?.peel_to_commit()?
+    };
+    Ok(commit)
+}
+
+/// Opens skeleton repositories with git config of the target repository
+///
+/// A shared fetcher fetches each URL once, and checks out the revisions from the fetched repository,
+/// so that the repositories of `git skel batch` can use different branches or tags of the same URL.
+struct Fetcher {
+    config: git2::Config,
+    fetched: Option<HashMap<String, Result<Fetched, String>>>,
+}
+
+type Fetched = (Repository, Origin, TempDir);
+
+impl Fetcher {
+    fn new(tgt: &Repository) -> Result<Fetcher, Error> {
+        Ok(Fetcher {
+            config: tgt.config()?.snapshot()?,
+            fetched: None,
+        })
+    }
+
+    fn shared(tgt: &Repository) -> Result<Fetcher, Error> {
+        Ok(Fetcher {
+            config: tgt.config()?.snapshot()?,
+            fetched: Some(HashMap::new()),
+        })
+    }
+
+    fn open(&mut self, url: &str, dir: &Path, dirty: bool) -> Result<(Repository, Origin), Error> {
+        let fetched = match self.fetched {
+            Some(ref mut x) => x,
+            None => return open_repo(&self.config, url, dir, dirty),
         };
+        let config = &self.config;
+        let entry = fetched.entry(String::from(url)).or_insert_with(|| {
+            let fetch = || -> Result<Fetched, Error> {
+                let fetch_dir = tempfile::tempdir()?;
+                let (repo, origin) = open_repo(config, url, fetch_dir.path(), dirty)?;
+                Ok((repo, origin, fetch_dir))
+            };
+            fetch().map_err(|x| {
+                let causes: Vec<_> = x.iter_chain().map(|x| format!("{}", x)).collect();
+                causes.join(": ")
+            })
+        });
+        match entry {
+            Ok((repo, origin, _)) => Ok((source::open_fetched(repo, dir)?, *origin)),
+            Err(x) => bail!("{}", x),
+        }
+    }
+}
 
-        src.checkout_tree(commit.as_object(), None)?;
-        src.set_head_detached(commit.id())?;
+/// Opens a local skeleton, an archive or a bundle directly, or clones a remote skeleton.
+fn open_repo(
+    config: &git2::Config,
+    url: &str,
+    path: &Path,
+    dirty: bool,
+) -> Result<(Repository, Origin), Error> {
+    if let Some(local) = source::local_path(url) {
+        let (repo, dirty) = source::open_local(&local, path, dirty)?;
+        let origin = if dirty { Origin::Dirty } else { Origin::Git };
+        return Ok((repo, origin));
     }
+    if dirty {
+        bail!(ErrorKind::DirtyNotLocal(String::from(url)));
+    }
+    if let Some(archive) = archive::path(url) {
+        return Ok((archive::open(&archive, path)?, Origin::Archive));
+    }
+    if let Some(bundle) = bundle::path(url) {
+        return Ok((bundle::open(&bundle, path)?, Origin::Git));
+    }
+    Ok((clone_repo(config, url, path)?, Origin::Git))
+}
 
-    Ok((src, dir))
+fn clone_repo(config: &git2::Config, url: &str, path: &Path) -> Result<Repository, Error> {
+    let url = remote::rewrite_url(config, url)?;
+    remote::setup_ssl(config, &url)?;
+
+    let auth = Auth::new();
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(auth.callbacks());
+    fetch_options.proxy_options(remote::proxy_options(config, &url)?);
+
+    let result = RepoBuilder::new()
+        .fetch_options(fetch_options)
+        .clone(&url, path);
+
+    match result {
+        Ok(x) => Ok(x),
+        Err(x) => match auth.error() {
+            Some(kind) => Err(Error::from(x).context(kind).into()),
+            None => Err(x.into()),
+        },
+    }
 }
 
-fn get_ignore(repo: &Repository) -> Result<Gitignore, Error> {
-    let root = PathBuf::from(repo.workdir().unwrap());
-    let path = root.join(".gitskelignore");
-    let mut builder = GitignoreBuilder::new(root);
-    builder.add(path);
-    builder.add_line(None, ".gitskelignore")?;
-    Ok(builder.build()?)
+fn warn_dirty(src: &Source, report: &Report) {
+    if src.dirty && report.is_human() {
+        eprintln!(
+            "warning: applied revision includes uncommitted changes: {}\n         Don't commit `.gitskel.toml` until `git skel update` without `--dirty`.",
+            src.revision
+        );
+    }
 }
 
-fn init(
-    src: &Repository,
-    tgt: &Repository,
-    src_ignore: &Gitignore,
-    tgt_ignore: &Gitignore,
-    force: bool,
-    dry_run: bool,
-) -> Result<(), Error> {
+/// Loads variables given by `--vars-file` and `--var` options.
+fn load_vars(vars: &[String], vars_file: Option<&Path>) -> Result<Table, Error> {
+    let mut ret = Table::new();
+    if let Some(path) = vars_file {
+        let s = fs::read_to_string(path)
+            .context(ErrorKind::VarsLoad(path.to_string_lossy().to_string()))?;
+        ret =
+            toml::from_str(&s).context(ErrorKind::VarsLoad(path.to_string_lossy().to_string()))?;
+    }
+    for var in vars {
+        let (key, value) = variable::parse_assignment(var)?;
+        ret.insert(key, value);
+    }
+    Ok(ret)
+}
+
+fn is_branch_not_found(error: &Error) -> bool {
+    error.iter_chain().any(|x| {
+        let kind = x.downcast_ref::<ErrorKind>().or_else(|| {
+            x.downcast_ref::<failure::Context<ErrorKind>>()
+                .map(|x| x.get_context())
+        });
+        matches!(kind, Some(ErrorKind::BranchNotFound(_)))
+    })
+}
+
+fn init(ctx: &Context, force: bool, dry_run: bool) -> Result<(), Error> {
     let mut warn = false;
-    for index in src.index()?.iter() {
+    for index in ctx.src.index()?.iter() {
         let path = PathBuf::from(&String::from_utf8(index.path)?);
-        warn |= file::copy(src, tgt, src_ignore, tgt_ignore, &path, &vec![], dry_run)?;
+        if !ctx.is_included(&path) {
+            continue;
+        }
+        warn |= file::copy(ctx, &path, &[], dry_run)?;
     }
 
     if warn && !force {
@@ -315,27 +1373,29 @@ fn init(
     Ok(())
 }
 
-fn update(
-    config: &mut Config,
-    src: &Repository,
-    tgt: &Repository,
-    src_ignore: &Gitignore,
-    tgt_ignore: &Gitignore,
-    force: bool,
-    dry_run: bool,
-) -> Result<(), Error> {
-    let src_obj = src.head()?.peel(ObjectType::Any)?;
-    let tgt_obj = src
-        .find_object(Oid::from_str(&config.revision)?, None)
-        .context(ErrorKind::RevisionNotFound(config.revision.clone()))?;
-
+fn update(ctx: &Context, force: bool, dry_run: bool) -> Result<(), Error> {
+    let src_obj = ctx.src.head()?.peel(ObjectType::Any)?;
     let src_tree = src_obj.peel_to_tree()?;
-    let tgt_tree = tgt_obj.peel_to_tree()?;
 
-    let diff = src.diff_tree_to_tree(Some(&tgt_tree), Some(&src_tree), None)?;
+    let diff = ctx
+        .src
+        .diff_tree_to_tree(ctx.base.as_ref(), Some(&src_tree), None)?;
+
+    let mut paths = Vec::new();
+    for index in ctx.src.index()?.iter() {
+        paths.push(PathBuf::from(&String::from_utf8(index.path)?));
+    }
 
     let mut warn = false;
-    let mut modified = Vec::new();
+    let mut modified: Vec<_> = ctx.rendered.iter().map(PathBuf::as_path).collect();
+
+    // Files enabled by conditions or components are treated as added by skeleton
+    for path in &paths {
+        if ctx.is_included(path) && !ctx.was_included(path) {
+            modified.push(path);
+        }
+    }
+
     for d in diff.deltas() {
         let mut delete = None;
 
@@ -355,38 +1415,47 @@ fn update(
         }
 
         if let Some(delete) = delete {
-            warn |= file::delete(tgt, src_ignore, tgt_ignore, delete, dry_run)?;
+            if ctx.was_included(delete) && ctx.is_in_scope(delete) {
+                warn |= file::delete(ctx, delete, dry_run)?;
+            }
         }
     }
 
-    for index in src.index()?.iter() {
-        let path = PathBuf::from(&String::from_utf8(index.path)?);
-        warn |= file::copy(src, tgt, src_ignore, tgt_ignore, &path, &modified, dry_run)?;
+    for path in &paths {
+        if !ctx.is_in_scope(path) {
+            continue;
+        }
+        if !ctx.is_included(path) {
+            // Files disabled by conditions or components are deleted if they were applied
+            let applied = ctx.was_included(path)
+                && ctx.base.as_ref().map(|x| x.get_path(path).is_ok()) == Some(true);
+            if applied {
+                warn |= file::delete(ctx, path, dry_run)?;
+            }
+            continue;
+        }
+        warn |= file::copy(ctx, path, &modified, dry_run)?;
     }
 
     if warn && !force {
-        bail!(ErrorKind::AbortByModified);
+        return Err(ErrorKind::AbortByModified.into());
     }
 
     Ok(())
 }
 
-fn clean(
-    src: &Repository,
-    tgt: &Repository,
-    src_ignore: &Gitignore,
-    tgt_ignore: &Gitignore,
-    force: bool,
-    dry_run: bool,
-) -> Result<(), Error> {
+fn clean(ctx: &Context, force: bool, dry_run: bool) -> Result<(), Error> {
     let mut warn = false;
-    for index in src.index()?.iter() {
+    for index in ctx.src.index()?.iter() {
         let path = PathBuf::from(&String::from_utf8(index.path)?);
-        warn |= file::delete(tgt, src_ignore, tgt_ignore, &path, dry_run)?;
+        if !ctx.is_included(&path) {
+            continue;
+        }
+        warn |= file::delete(ctx, &path, dry_run)?;
     }
 
     if warn && !force {
-        bail!(ErrorKind::AbortByModified);
+        return Err(ErrorKind::AbortByModified.into());
     }
 
     Ok(())
@@ -418,27 +1487,141 @@ fn main() {
                 x
             ));
         }
-        std::process::exit(1);
+        let code = x
+            .downcast_ref::<ErrorKind>()
+            .map(ErrorKind::exit_code)
+            .unwrap_or(1);
+        std::process::exit(code);
     }
 }
 
 fn run(opt: Opt) -> Result<(), Error> {
-    match opt {
-        Opt::Init {
+    let report = Report::new(opt.format, opt.command.name());
+    let ret = run_command(opt.command, &report);
+    report.finish(ret.as_ref().err());
+    ret
+}
+
+/// Runs the command.
+///
+/// The user config is loaded only by the commands using it, so that a broken one doesn't affect the others.
+fn run_command(command: Command, report: &Report) -> Result<(), Error> {
+    match command {
+        Command::Init {
             url,
             branch,
             tag,
             force,
+            trust_hooks,
+            no_hooks,
+            vars,
+            vars_file,
+            dirty,
         } => cmd_init(
             &url,
             branch.as_ref().map(String::as_ref),
             tag.as_ref().map(String::as_ref),
             force,
+            HookMode::new(trust_hooks, no_hooks),
+            &Changes {
+                vars: load_vars(&vars, vars_file.as_deref())?,
+                dirty,
+                ..Default::default()
+            },
+            &UserConfig::load()?,
+            report,
+        )?,
+        Command::Update {
+            force,
+            trust_hooks,
+            no_hooks,
+            dirty,
+        } => cmd_update(
+            force,
+            HookMode::new(trust_hooks, no_hooks),
+            &Changes {
+                dirty,
+                ..Default::default()
+            },
+            &UserConfig::load()?,
+            report,
+        )?,
+        Command::Branch {
+            branch,
+            force,
+            trust_hooks,
+            no_hooks,
+        } => cmd_branch(
+            &branch,
+            force,
+            HookMode::new(trust_hooks, no_hooks),
+            &UserConfig::load()?,
+            report,
+        )?,
+        Command::Tag {
+            tag,
+            force,
+            trust_hooks,
+            no_hooks,
+        } => cmd_tag(
+            &tag,
+            force,
+            HookMode::new(trust_hooks, no_hooks),
+            &UserConfig::load()?,
+            report,
+        )?,
+        Command::Status => cmd_status(report)?,
+        Command::Check => cmd_check(&UserConfig::load()?, report)?,
+        Command::ExportPatch { output } => {
+            cmd_export_patch(output.as_deref(), &UserConfig::load()?, report)?
+        }
+        Command::Batch {
+            manifest,
+            force,
+            trust_hooks,
+            no_hooks,
+        } => cmd_batch(
+            &manifest,
+            force,
+            HookMode::new(trust_hooks, no_hooks),
+            &UserConfig::load()?,
+            report,
+        )?,
+        Command::Vars {
+            command: VarsCommand::Set { vars, force },
+        } => cmd_vars_set(
+            &load_vars(&vars, None)?,
+            force,
+            &UserConfig::load()?,
+            report,
         )?,
-        Opt::Update { force } => cmd_update(force)?,
-        Opt::Branch { branch, force } => cmd_branch(&branch, force)?,
-        Opt::Tag { tag, force } => cmd_tag(&tag, force)?,
-        Opt::Clean { force } => cmd_clean(force)?,
+        Command::Component {
+            command: ComponentCommand::Enable { name, force },
+        } => cmd_component(&name, true, force, &UserConfig::load()?, report)?,
+        Command::Component {
+            command: ComponentCommand::Disable { name, force },
+        } => cmd_component(&name, false, force, &UserConfig::load()?, report)?,
+        Command::Config {
+            show_origin,
+            command: None,
+        } => cmd_config_list(show_origin, report)?,
+        Command::Config {
+            command: Some(ConfigCommand::Validate),
+            ..
+        } => cmd_config_validate(report)?,
+        Command::Config {
+            show_origin,
+            command:
+                Some(ConfigCommand::Get {
+                    key,
+                    show_origin: x,
+                }),
+        } => cmd_config_get(&key, show_origin || x, report)?,
+        Command::Config {
+            command: Some(ConfigCommand::Set { key, value }),
+            ..
+        } => cmd_config_set(&key, &value, report)?,
+        Command::Clean { force } => cmd_clean(force, &UserConfig::load()?, report)?,
     }
 
     Ok(())
@@ -447,6 +1630,7 @@ fn run(opt: Opt) -> Result<(), Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Mutex;
 
     fn init() {
         let args = vec![
@@ -512,8 +1696,272 @@ mod tests {
         assert!(ret.is_ok());
     }
 
+    /// Commands run in the current directory, so the tests must not run in parallel
+    static CWD: Mutex<()> = Mutex::new(());
+
+    fn commit_all(repo: &Repository) {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"].iter(), None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("git-skel", "git-skel").unwrap();
+        let parent = repo.head().ok().and_then(|x| x.peel_to_commit().ok());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_once() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let skel = dir.path().join("skel");
+        let proj = dir.path().join("proj");
+
+        let skel_repo = Repository::init(&skel).unwrap();
+        fs::write(skel.join("LICENSE"), "license").unwrap();
+        fs::write(
+            skel.join(".gitskel-template.toml"),
+            "[[strategy]]\npath = \"LICENSE\"\nstrategy = \"create-once\"\n",
+        )
+        .unwrap();
+        commit_all(&skel_repo);
+        let proj_repo = Repository::init(&proj).unwrap();
+        fs::write(proj.join("README.md"), "readme").unwrap();
+        commit_all(&proj_repo);
+
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&proj).unwrap();
+        let init = run(Opt::from_iter(
+            ["git-skel", "init", skel.to_str().unwrap()].iter(),
+        ));
+        let created = proj.join("LICENSE").exists();
+
+        // The file deleted by the user is not created again even if skeleton updates it
+        commit_all(&proj_repo);
+        fs::remove_file(proj.join("LICENSE")).unwrap();
+        commit_all(&proj_repo);
+        fs::write(skel.join("LICENSE"), "license v2").unwrap();
+        commit_all(&skel_repo);
+        let update = run(Opt::from_iter(["git-skel", "update"].iter()));
+        std::env::set_current_dir(cwd).unwrap();
+
+        assert!(init.is_ok());
+        assert!(created);
+        assert!(update.is_ok());
+        assert!(!proj.join("LICENSE").exists());
+    }
+
+    #[test]
+    fn test_merge_uncommitted() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let skel = dir.path().join("skel");
+        let proj = dir.path().join("proj");
+
+        let skel_repo = Repository::init(&skel).unwrap();
+        fs::write(skel.join("Makefile"), "a\nb\nc\n").unwrap();
+        fs::write(
+            skel.join(".gitskel-template.toml"),
+            "[[strategy]]\npath = \"Makefile\"\nstrategy = \"merge\"\n",
+        )
+        .unwrap();
+        commit_all(&skel_repo);
+        let proj_repo = Repository::init(&proj).unwrap();
+        fs::write(proj.join("README.md"), "readme").unwrap();
+        commit_all(&proj_repo);
+
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&proj).unwrap();
+        let init = run(Opt::from_iter(
+            ["git-skel", "init", skel.to_str().unwrap()].iter(),
+        ));
+
+        // Uncommitted changes are not merged without `--force` even if the merge has no conflict
+        commit_all(&proj_repo);
+        fs::write(proj.join("Makefile"), "a\nb\nc\nd\n").unwrap();
+        fs::write(skel.join("Makefile"), "x\nb\nc\n").unwrap();
+        commit_all(&skel_repo);
+        let update = run(Opt::from_iter(["git-skel", "update"].iter()));
+        let kept = fs::read_to_string(proj.join("Makefile")).unwrap();
+        let force = run(Opt::from_iter(["git-skel", "update", "--force"].iter()));
+        std::env::set_current_dir(cwd).unwrap();
+
+        assert!(init.is_ok());
+        // The abort is classified as conflicts by `git skel batch`
+        assert_eq!(
+            update.map_err(|x| Outcome::from_error(&x)),
+            Err(Outcome::Conflicts)
+        );
+        assert_eq!(kept, "a\nb\nc\nd\n");
+        assert!(force.is_ok());
+        assert_eq!(
+            fs::read_to_string(proj.join("Makefile")).unwrap(),
+            "x\nb\nc\nd\n"
+        );
+    }
+
+    #[test]
+    fn test_template_base() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let skel = dir.path().join("skel");
+        let proj = dir.path().join("proj");
+
+        let skel_repo = Repository::init(&skel).unwrap();
+        let block = |x: &str| {
+            format!(
+                "# >>> git-skel\nname = {{{{ name }}}}\n{}# <<< git-skel\n",
+                x
+            )
+        };
+        fs::write(skel.join("Makefile"), block("")).unwrap();
+        fs::write(
+            skel.join(".gitskel-template.toml"),
+            "templates = [\"Makefile\"]\n\n[[variable]]\nname = \"name\"\ndefault = \"demo\"\n\n\
+             [[strategy]]\npath = \"Makefile\"\nstrategy = \"block\"\n",
+        )
+        .unwrap();
+        commit_all(&skel_repo);
+        let proj_repo = Repository::init(&proj).unwrap();
+        fs::write(proj.join("README.md"), "readme").unwrap();
+        commit_all(&proj_repo);
+
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&proj).unwrap();
+        let init = run(Opt::from_iter(
+            ["git-skel", "init", skel.to_str().unwrap()].iter(),
+        ));
+
+        // The rendered block is not reported as modified by comparing with the unrendered base
+        commit_all(&proj_repo);
+        fs::write(skel.join("Makefile"), block("all:\n")).unwrap();
+        commit_all(&skel_repo);
+        let update = run(Opt::from_iter(["git-skel", "update"].iter()));
+        std::env::set_current_dir(cwd).unwrap();
+
+        assert!(init.is_ok());
+        assert!(update.is_ok());
+        assert_eq!(
+            fs::read_to_string(proj.join("Makefile")).unwrap(),
+            "# >>> git-skel\nname = demo\nall:\n# <<< git-skel\n"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let skel = dir.path().join("skel");
+        let proj = dir.path().join("proj");
+
+        let skel_repo = Repository::init(&skel).unwrap();
+        fs::write(skel.join("LICENSE"), "license").unwrap();
+        commit_all(&skel_repo);
+        let proj_repo = Repository::init(&proj).unwrap();
+        fs::write(proj.join("README.md"), "readme").unwrap();
+        commit_all(&proj_repo);
+
+        let check = || {
+            run(Opt::from_iter(["git-skel", "check"].iter()))
+                .err()
+                .map(|x| x.downcast_ref::<ErrorKind>().map(ErrorKind::exit_code))
+        };
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&proj).unwrap();
+        let init = run(Opt::from_iter(
+            ["git-skel", "init", skel.to_str().unwrap()].iter(),
+        ));
+        commit_all(&proj_repo);
+        let exclude = fs::read_to_string(proj_repo.path().join("info/exclude")).unwrap();
+        let up_to_date = check();
+
+        fs::write(skel.join("LICENSE"), "license v2").unwrap();
+        commit_all(&skel_repo);
+        let update_available = check();
+
+        // Drift takes priority over an available update
+        fs::write(proj.join("LICENSE"), "modified").unwrap();
+        commit_all(&proj_repo);
+        let drift = check();
+        std::env::set_current_dir(cwd).unwrap();
+
+        assert!(init.is_ok());
+        assert_eq!(up_to_date, None);
+        assert_eq!(update_available, Some(Some(2)));
+        assert_eq!(drift, Some(Some(3)));
+        // The check never writes the repository
+        assert_eq!(
+            fs::read_to_string(proj_repo.path().join("info/exclude")).unwrap(),
+            exclude
+        );
+        assert_eq!(
+            fs::read_to_string(proj.join("LICENSE")).unwrap(),
+            "modified"
+        );
+    }
+
+    #[test]
+    fn test_export_patch_output() {
+        // The patch series can't be written to stdout with the report of machine readable formats
+        for format in ["json", "ndjson"] {
+            let ret = run(Opt::from_iter(
+                ["git-skel", "--format", format, "export-patch"].iter(),
+            ));
+            let kind = ret
+                .as_ref()
+                .err()
+                .and_then(|x| x.downcast_ref::<ErrorKind>());
+            assert!(matches!(kind, Some(ErrorKind::PatchOutputRequired)));
+        }
+    }
+
+    #[test]
+    fn test_relative_url() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let skel = dir.path().join("skel");
+        let proj = dir.path().join("proj");
+
+        let skel_repo = Repository::init(&skel).unwrap();
+        fs::write(skel.join("LICENSE"), "license").unwrap();
+        commit_all(&skel_repo);
+        let proj_repo = Repository::init(&proj).unwrap();
+        fs::create_dir(proj.join("src")).unwrap();
+        fs::write(proj.join("src/main.rs"), "fn main() {}").unwrap();
+        commit_all(&proj_repo);
+
+        let cwd = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&proj).unwrap();
+        let init = run(Opt::from_iter(["git-skel", "init", "../skel"].iter()));
+        let config = Config::load(&proj_repo).unwrap();
+
+        // The relative path is resolved when it is recorded, not against the working directory of update
+        commit_all(&proj_repo);
+        fs::write(skel.join("LICENSE"), "license v2").unwrap();
+        commit_all(&skel_repo);
+        std::env::set_current_dir(proj.join("src")).unwrap();
+        let update = run(Opt::from_iter(["git-skel", "update"].iter()));
+        std::env::set_current_dir(cwd).unwrap();
+
+        assert!(init.is_ok());
+        assert_eq!(
+            Path::new(&config.url),
+            fs::canonicalize(&skel).unwrap().as_path()
+        );
+        assert!(update.is_ok());
+        assert_eq!(
+            fs::read_to_string(proj.join("LICENSE")).unwrap(),
+            "license v2"
+        );
+    }
+
     #[test]
     fn test_sequence() {
+        let _lock = CWD.lock().unwrap_or_else(|x| x.into_inner());
         // normal
         init();
         update();
@@ -0,0 +1,242 @@
+use crate::component::{Component, Components};
+use crate::condition::Conditions;
+use crate::error::ErrorKind;
+use crate::strategy::Rule;
+use crate::variable::Variable;
+use failure::{bail, Error, ResultExt};
+use git2::Repository;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = ".gitskel-template.toml";
+
+/// Skeleton manifest put at the root of skeleton repository as `.gitskel-template.toml`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Parent skeleton which this skeleton extends
+    pub parent: Option<Parent>,
+    /// Minimum version of git-skel required by the skeleton
+    pub min_version: Option<String>,
+    /// Default ignore patterns in addition to `.gitskelignore`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default, rename = "variable")]
+    pub variables: Vec<Variable>,
+    /// Files rendered with variables
+    #[serde(default)]
+    pub templates: Vec<String>,
+    /// Files included only when the condition is true ( `<glob> when <expression>` )
+    #[serde(default)]
+    pub conditions: Vec<String>,
+    #[serde(default, rename = "component")]
+    pub components: Vec<Component>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default, rename = "strategy")]
+    pub strategies: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Parent {
+    pub url: String,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_apply: Vec<String>,
+    #[serde(default)]
+    pub post_apply: Vec<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_apply.is_empty() && self.post_apply.is_empty()
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &String> {
+        self.pre_apply.iter().chain(self.post_apply.iter())
+    }
+}
+
+impl Manifest {
+    pub fn load(src: &Repository) -> Result<Manifest, Error> {
+        let src_root = PathBuf::from(src.workdir().unwrap());
+        let manifest_path = src_root.join(MANIFEST_FILE);
+
+        if !manifest_path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        fn load_file(path: &Path) -> Result<Manifest, Error> {
+            let s = fs::read_to_string(path)?;
+            let value: toml::Table = toml::from_str(&s)?;
+
+            // `min_version` is checked first because newer manifest may not be parsed
+            if let Some(min_version) = value.get("min_version").and_then(|x| x.as_str()) {
+                check_version(min_version)?;
+            }
+
+            let manifest: Manifest = value.try_into()?;
+            manifest.validate()?;
+            Ok(manifest)
+        }
+
+        let manifest = load_file(&manifest_path).context(ErrorKind::ManifestLoad(
+            manifest_path.to_string_lossy().to_string(),
+        ))?;
+        Ok(manifest)
+    }
+
+    /// Returns the parent skeleton declared by the skeleton itself.
+    ///
+    /// The manifest is not validated because the parts may be declared by the parent.
+    pub fn parent(src: &Repository) -> Result<Option<Parent>, Error> {
+        let manifest_path = PathBuf::from(src.workdir().unwrap()).join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        fn load_parent(path: &Path) -> Result<Option<Parent>, Error> {
+            let s = fs::read_to_string(path)?;
+            let mut value: toml::Table = toml::from_str(&s)?;
+            match value.remove("parent") {
+                Some(x) => Ok(Some(x.try_into()?)),
+                None => Ok(None),
+            }
+        }
+
+        let parent = load_parent(&manifest_path).context(ErrorKind::ManifestLoad(
+            manifest_path.to_string_lossy().to_string(),
+        ))?;
+        Ok(parent)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut names = HashSet::new();
+        for var in &self.variables {
+            var.validate()?;
+            if !names.insert(&var.name) {
+                bail!("variable is declared twice: {}", var.name);
+            }
+        }
+        for name in Conditions::new(&self.conditions)?.variables() {
+            if !names.iter().any(|x| x.as_str() == name) {
+                bail!("variable is not declared: {}", name);
+            }
+        }
+        Components::new(&self.components)?;
+        Ok(())
+    }
+}
+
+/// Merges the manifest of a child skeleton into the manifest of the parent skeleton.
+///
+/// Values of the child take precedence, and variables and components declared by the child replace the ones with the same name.
+/// Hooks of the parent run before hooks of the child.
+pub fn merge(child: &str, parent: &str) -> Result<String, Error> {
+    let mut child: toml::Table = toml::from_str(child)?;
+    let parent: toml::Table = toml::from_str(parent)?;
+    child.remove("parent");
+    merge_table(&mut child, parent, false);
+    Ok(toml::to_string(&child)?)
+}
+
+fn merge_table(child: &mut toml::Table, parent: toml::Table, parent_first: bool) {
+    for (key, value) in parent {
+        if key == "parent" {
+            continue;
+        }
+        let hooks = key == "hooks";
+        match (child.get_mut(&key), value) {
+            (None, value) => {
+                child.insert(key, value);
+            }
+            (Some(toml::Value::Table(x)), toml::Value::Table(y)) => merge_table(x, y, hooks),
+            (Some(toml::Value::Array(x)), toml::Value::Array(y)) => {
+                let name =
+                    |v: &toml::Value| v.get("name").and_then(|n| n.as_str()).map(String::from);
+                let names: HashSet<String> = x.iter().filter_map(name).collect();
+                let y = y
+                    .into_iter()
+                    .filter(|v| name(v).is_none_or(|n| !names.contains(&n)));
+                if parent_first {
+                    let mut merged: Vec<toml::Value> = y.collect();
+                    merged.append(x);
+                    *x = merged;
+                } else {
+                    x.extend(y);
+                }
+            }
+            (Some(toml::Value::String(x)), toml::Value::String(y)) if key == "min_version" => {
+                // the newer requirement of both layers is required
+                if let (Ok(a), Ok(b)) = (Version::parse(x), Version::parse(&y)) {
+                    if a < b {
+                        *x = y;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn check_version(min_version: &str) -> Result<(), Error> {
+    let min_version = Version::parse(min_version)?;
+    let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+    // pre-release of the current version satisfies the same version
+    let current = Version::new(current.major, current.minor, current.patch);
+    if current < min_version {
+        return Err(ErrorKind::VersionRequired(format!("{}", min_version)).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let parent = r#"
+            min_version = "0.1.5"
+            ignore = ["LICENSE"]
+            [[variable]]
+            name = "owner"
+            default = "org"
+            [[variable]]
+            name = "license"
+            default = "MIT"
+            [hooks]
+            post_apply = ["parent"]
+        "#;
+        let child = r#"
+            min_version = "0.1.3"
+            ignore = ["Cargo.lock"]
+            [parent]
+            url = "https://example.com/parent.git"
+            [[variable]]
+            name = "license"
+            default = "Apache-2.0"
+            [hooks]
+            post_apply = ["child"]
+        "#;
+
+        let merged: Manifest = toml::from_str(&merge(child, parent).unwrap()).unwrap();
+        assert!(merged.parent.is_none());
+        assert_eq!(merged.min_version.as_deref(), Some("0.1.5"));
+        assert_eq!(merged.ignore, vec!["Cargo.lock", "LICENSE"]);
+        let names: Vec<_> = merged.variables.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["license", "owner"]);
+        assert_eq!(
+            merged.variables[0].default,
+            Some(toml::Value::from("Apache-2.0"))
+        );
+        assert_eq!(merged.hooks.post_apply, vec!["parent", "child"]);
+    }
+}
@@ -0,0 +1,102 @@
+use failure::Error;
+use git2::build::TreeUpdateBuilder;
+use git2::{Commit, Email, EmailCreateOptions, FileMode, Repository, Signature};
+use std::path::PathBuf;
+
+/// Creates a `git am` compatible patch series from `base` commit of skeleton repository.
+///
+/// Each file becomes a patch, and the patches are applied in order.
+pub fn series(
+    src: &Repository,
+    base: &Commit,
+    files: &[(PathBuf, Vec<u8>)],
+    author: &Signature,
+) -> Result<Vec<u8>, Error> {
+    let mut ret = Vec::new();
+    let mut parent = base.clone();
+
+    for (i, (path, content)) in files.iter().enumerate() {
+        let parent_tree = parent.tree()?;
+        let mode = match parent_tree.get_path(path).map(|x| x.filemode()) {
+            Ok(0o100_755) => FileMode::BlobExecutable,
+            _ => FileMode::Blob,
+        };
+
+        let blob = src.blob(content)?;
+        let mut builder = TreeUpdateBuilder::new();
+        builder.upsert(path.as_path(), blob, mode);
+        let tree = src.find_tree(builder.create_updated(src, &parent_tree)?)?;
+
+        let summary = format!("Update {}", path.to_string_lossy().replace('\\', "/"));
+        let id = src.commit(None, author, author, &summary, &tree, &[&parent])?;
+        let diff = src.diff_tree_to_tree(Some(&parent_tree), Some(&tree), None)?;
+
+        let mut opts = EmailCreateOptions::new();
+        opts.always_number(true);
+        let email = Email::from_diff(
+            &diff,
+            i + 1,
+            files.len(),
+            &id,
+            summary.as_str(),
+            "",
+            author,
+            &mut opts,
+        )?;
+        ret.extend_from_slice(email.as_slice());
+
+        parent = src.find_commit(id)?;
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let author = Signature::now("skel", "skel@example.com").unwrap();
+
+        let mut builder = TreeUpdateBuilder::new();
+        builder.upsert("README.md", repo.blob(b"readme\n").unwrap(), FileMode::Blob);
+        let empty = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let tree = repo
+            .find_tree(builder.create_updated(&repo, &empty).unwrap())
+            .unwrap();
+        let base = repo
+            .commit(None, &author, &author, "base", &tree, &[])
+            .unwrap();
+        let base = repo.find_commit(base).unwrap();
+
+        let files = vec![
+            (PathBuf::from("src/main.rs"), Vec::from("fn main() {}\n")),
+            (PathBuf::from("README.md"), Vec::from("readme v2\n")),
+        ];
+        let series = series(&repo, &base, &files, &author).unwrap();
+        let series = String::from_utf8(series).unwrap();
+
+        // Each file becomes a patch in the given order
+        let subjects: Vec<_> = series
+            .lines()
+            .filter(|x| x.starts_with("Subject:"))
+            .collect();
+        assert_eq!(
+            subjects,
+            vec![
+                "Subject: [PATCH 1/2] Update src/main.rs",
+                "Subject: [PATCH 2/2] Update README.md",
+            ]
+        );
+        assert!(series.contains("+fn main() {}"));
+        assert!(series.contains("-readme\n+readme v2"));
+
+        // The commits of the series are never referred by any branch
+        assert!(repo.head().is_err());
+    }
+}
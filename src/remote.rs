@@ -0,0 +1,177 @@
+use crate::error::ErrorKind;
+use failure::{bail, Error};
+use git2::{Config, ProxyOptions};
+use regex::Regex;
+use std::path::Path;
+
+/// Rewrites `url` by `url.<base>.insteadOf` of git config.
+///
+/// If some prefixes match, the longest one is used as the same as git.
+pub fn rewrite_url(config: &Config, url: &str) -> Result<String, Error> {
+    let mut rewrite: Option<(String, String)> = None;
+
+    let mut entries = config.entries(Some(r"^url\..*\.insteadof$"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (name, prefix) = match (entry.name(), entry.value()) {
+            (Some(name), Some(prefix)) => (name, prefix),
+            _ => continue,
+        };
+        let base = &name["url.".len()..name.len() - ".insteadof".len()];
+
+        let longer = rewrite
+            .as_ref()
+            .map(|(x, _)| prefix.len() > x.len())
+            .unwrap_or(true);
+        if url.starts_with(prefix) && longer {
+            rewrite = Some((String::from(prefix), String::from(base)));
+        }
+    }
+
+    match rewrite {
+        Some((prefix, base)) => Ok(format!("{}{}", base, &url[prefix.len()..])),
+        None => Ok(String::from(url)),
+    }
+}
+
+/// Checks the syntax of `url`.
+///
+/// Existing local paths, `scheme://host/path` and scp-like `user@host:path` are accepted as the same as git.
+pub fn check_url(url: &str) -> Result<(), Error> {
+    let path = url.trim_start_matches("file://");
+    let valid = if url.starts_with("file://") {
+        Path::new(path).exists()
+    } else if url.contains("://") {
+        Regex::new(r"^(https?|ssh|git)://[^/\s]+/\S+$")?.is_match(url)
+    } else {
+        // scp-like syntax requires user or domain name to reject typos of scheme like `htps:/`
+        Path::new(path).exists()
+            || Regex::new(r"^[^@/:\s]+@[A-Za-z0-9.\-]+:\S+$")?.is_match(url)
+            || Regex::new(r"^[A-Za-z0-9\-]+\.[A-Za-z0-9.\-]+:\S+$")?.is_match(url)
+    };
+    if valid {
+        Ok(())
+    } else {
+        bail!(ErrorKind::UrlInvalid(String::from(url)))
+    }
+}
+
+/// Returns the proxy setting from `http.<url>.proxy` or `http.proxy` of git config.
+///
+/// If not configured, the proxy is detected automatically from environment variables.
+pub fn proxy_options(config: &Config, url: &str) -> Result<ProxyOptions<'static>, Error> {
+    let mut options = ProxyOptions::new();
+    match get_http(config, url, "proxy")? {
+        Some(ref proxy) if !proxy.is_empty() => {
+            options.url(proxy);
+        }
+        _ => {
+            options.auto();
+        }
+    }
+    Ok(options)
+}
+
+/// Sets the CA bundle from `http.sslCAInfo` or `http.sslCAPath` of git config.
+pub fn setup_ssl(config: &Config, url: &str) -> Result<(), Error> {
+    // libgit2 options are process global, and git-skel clones repositories in a single thread.
+    if let Some(file) = get_http(config, url, "sslcainfo")? {
+        unsafe { git2::opts::set_ssl_cert_file(file)? };
+    }
+    if let Some(path) = get_http(config, url, "sslcapath")? {
+        unsafe { git2::opts::set_ssl_cert_dir(path)? };
+    }
+    Ok(())
+}
+
+fn get_http(config: &Config, url: &str, key: &str) -> Result<Option<String>, Error> {
+    let mut value: Option<(usize, String)> = None;
+
+    let mut entries = config.entries(Some(&format!(r"^http\..*\.{}$", key)))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (name, x) = match (entry.name(), entry.value()) {
+            (Some(name), Some(x)) => (name, x),
+            _ => continue,
+        };
+        let prefix = &name["http.".len()..name.len() - key.len() - 1];
+
+        let longer = value
+            .as_ref()
+            .map(|(len, _)| prefix.len() >= *len)
+            .unwrap_or(true);
+        if url.starts_with(prefix) && longer {
+            value = Some((prefix.len(), String::from(x)));
+        }
+    }
+
+    if let Some((_, x)) = value {
+        return Ok(Some(x));
+    }
+
+    match config.get_string(&format!("http.{}", key)) {
+        Ok(x) => Ok(Some(x)),
+        Err(ref x) if x.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(x) => Err(x.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::open(&dir.path().join("config")).unwrap();
+        config
+            .set_str(
+                "url.https://mirror.example/.insteadof",
+                "https://github.com/",
+            )
+            .unwrap();
+        config
+            .set_str(
+                "url.https://mirror.example/dalance/.insteadof",
+                "https://github.com/dalance/",
+            )
+            .unwrap();
+        config
+            .set_str("http.https://github.com/.proxy", "http://proxy:8080")
+            .unwrap();
+
+        assert_eq!(
+            rewrite_url(&config, "https://github.com/dalance/git-skel.git").unwrap(),
+            "https://mirror.example/dalance/git-skel.git"
+        );
+        assert_eq!(
+            rewrite_url(&config, "https://github.com/rust-lang/rust.git").unwrap(),
+            "https://mirror.example/rust-lang/rust.git"
+        );
+        assert_eq!(
+            rewrite_url(&config, "https://gitlab.com/x/y.git").unwrap(),
+            "https://gitlab.com/x/y.git"
+        );
+        assert_eq!(
+            get_http(&config, "https://github.com/x/y.git", "proxy").unwrap(),
+            Some(String::from("http://proxy:8080"))
+        );
+        assert_eq!(
+            get_http(&config, "https://gitlab.com/x/y.git", "proxy").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_url() {
+        assert!(check_url("https://github.com/dalance/git-skel.git").is_ok());
+        assert!(check_url("ssh://git@github.com:22/dalance/git-skel.git").is_ok());
+        assert!(check_url("git@github.com:dalance/git-skel.git").is_ok());
+        assert!(check_url(".").is_ok());
+        assert!(check_url("https//github.com/dalance/git-skel.git").is_err());
+        assert!(check_url("ftp://github.com/dalance/git-skel.git").is_err());
+        assert!(check_url("github.com:dalance/git-skel.git").is_ok());
+        assert!(check_url("htps:/github.com/dalance/git-skel.git").is_err());
+        assert!(check_url("./not-found").is_err());
+    }
+}
@@ -0,0 +1,727 @@
+use crate::batch::Outcome;
+use crate::strategy::Strategy;
+use failure::{format_err, Error};
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format_err!("unknown format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Detect,
+    Apply,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Copy,
+    Delete,
+    Merge,
+    Block,
+    Unblock,
+    Keep,
+    Ignore,
+    Missing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Changed by skeleton
+    Skeleton,
+    /// The local file has uncommitted changes
+    Uncommitted,
+    /// The local file exists, but is not tracked
+    Untracked,
+    /// The local file is modified though skeleton didn't change it
+    LocallyModified,
+    /// Three-way merge has conflicts
+    Conflict,
+    /// Kept by the strategy
+    Strategy,
+    /// Matched with `.gitskelignore`
+    Ignored,
+    /// The local file doesn't exist
+    NotFound,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IgnoreSource {
+    Skeleton,
+    /// Matched with `ignore` of the user config
+    User,
+    Project,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LocalStatus {
+    Clean,
+    Modified,
+    Added,
+    Deleted,
+    Untracked,
+    Ignored,
+    Missing,
+}
+
+impl LocalStatus {
+    pub fn new(status: &Result<git2::Status, git2::Error>, exists: bool) -> LocalStatus {
+        match status {
+            Ok(x) if x.is_empty() => LocalStatus::Clean,
+            Ok(x) if x.is_wt_new() => LocalStatus::Untracked,
+            Ok(x) if x.is_index_new() => LocalStatus::Added,
+            Ok(x) if x.is_wt_deleted() || x.is_index_deleted() => LocalStatus::Deleted,
+            Ok(x) if x.is_ignored() => LocalStatus::Ignored,
+            Ok(_) => LocalStatus::Modified,
+            Err(_) if exists => LocalStatus::Untracked,
+            Err(_) => LocalStatus::Missing,
+        }
+    }
+}
+
+/// Planned or applied action to a file
+#[derive(Clone, Debug, Serialize)]
+pub struct Action {
+    pub path: String,
+    pub action: Kind,
+    pub reason: Reason,
+    pub ignore: Option<IgnoreSource>,
+    pub status: LocalStatus,
+    pub strategy: Strategy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+}
+
+impl Action {
+    pub fn new(
+        path: &Path,
+        action: Kind,
+        reason: Reason,
+        ignore: Option<IgnoreSource>,
+        status: LocalStatus,
+        strategy: Strategy,
+    ) -> Action {
+        Action {
+            path: path.to_string_lossy().replace('\\', "/"),
+            action,
+            reason,
+            ignore,
+            status,
+            strategy,
+            repository: None,
+        }
+    }
+
+    /// Returns true if the action should be aborted without `--force`.
+    pub fn warn(&self) -> bool {
+        matches!(
+            self.reason,
+            Reason::Uncommitted | Reason::Untracked | Reason::Conflict | Reason::LocallyModified
+        )
+    }
+
+    /// Returns true if the action changes the local file.
+    pub fn is_change(&self) -> bool {
+        !matches!(self.action, Kind::Keep | Kind::Ignore | Kind::Missing)
+    }
+
+    /// Returns true if the action restores a file enforced by skeleton.
+    pub fn is_drift(&self) -> bool {
+        matches!(self.action, Kind::Copy | Kind::Block)
+    }
+
+    fn indicator(&self) -> String {
+        let mark = match self.reason {
+            Reason::Uncommitted | Reason::Untracked | Reason::Conflict => "!",
+            Reason::LocallyModified => "*",
+            _ => " ",
+        };
+        match self.action {
+            Kind::Copy => format!("{}copy  ", mark),
+            Kind::Delete => format!("{}delete", mark),
+            Kind::Merge => format!("{}merge ", mark),
+            Kind::Block => format!("{}block ", mark),
+            Kind::Unblock => String::from("unblock"),
+            Kind::Keep => String::from(" keep  "),
+            Kind::Ignore => String::from(" ignore"),
+            Kind::Missing => String::from("missing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Hook {
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+}
+
+/// Result of `git skel status`
+#[derive(Clone, Debug, Serialize)]
+pub struct Status {
+    pub url: String,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub applied_revision: String,
+    pub applied_tag: Option<String>,
+    pub latest_revision: String,
+    pub latest_tag: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<Parent>,
+    pub up_to_date: bool,
+}
+
+/// Parent skeleton in the result of `git skel status`
+#[derive(Clone, Debug, Serialize)]
+pub struct Parent {
+    pub url: String,
+    pub applied_revision: Option<String>,
+    pub latest_revision: String,
+}
+
+/// Result of `git skel check`
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub result: &'static str,
+    pub applied_revision: String,
+    pub latest_revision: String,
+    pub up_to_date: bool,
+    pub drift: Vec<String>,
+}
+
+/// Result of each check of `git skel config validate`
+#[derive(Clone, Debug, Serialize)]
+pub struct Validation {
+    pub item: &'static str,
+    pub error: Option<String>,
+}
+
+/// Value of `git skel config` with the file where it comes from
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub key: String,
+    pub value: toml::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Repository {
+    pub path: String,
+    pub result: &'static str,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Summary {
+    pub result: &'static str,
+    pub planned: BTreeMap<String, usize>,
+    pub applied: BTreeMap<String, usize>,
+    pub warnings: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+struct Document {
+    command: &'static str,
+    old_revision: Option<String>,
+    new_revision: Option<String>,
+    planned: Vec<Action>,
+    applied: Vec<Action>,
+    hooks: Vec<Hook>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    patches: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check: Option<Check>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    validations: Vec<Validation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<toml::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entries: Vec<Entry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    repositories: Vec<Repository>,
+    summary: Summary,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Event<'a> {
+    Phase {
+        phase: Phase,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repository: Option<&'a str>,
+    },
+    Revisions {
+        old_revision: Option<&'a str>,
+        new_revision: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repository: Option<&'a str>,
+    },
+    Action {
+        phase: Phase,
+        #[serde(flatten)]
+        action: &'a Action,
+    },
+    Hook(&'a Hook),
+    Patch {
+        path: &'a str,
+    },
+    Status(&'a Status),
+    Check(&'a Check),
+    Validation(&'a Validation),
+    Value {
+        key: &'a str,
+        value: &'a toml::Value,
+    },
+    Entry(&'a Entry),
+    Repository(&'a Repository),
+    Summary(&'a Summary),
+}
+
+/// Collects the actions of the command, and renders them as human readable text, JSON or NDJSON.
+pub struct Report {
+    format: OutputFormat,
+    phase: RefCell<Phase>,
+    repository: RefCell<Option<String>>,
+    doc: RefCell<Document>,
+}
+
+impl Report {
+    pub fn new(format: OutputFormat, command: &'static str) -> Report {
+        let doc = Document {
+            command,
+            ..Default::default()
+        };
+        Report {
+            format,
+            phase: RefCell::new(Phase::Detect),
+            repository: RefCell::new(None),
+            doc: RefCell::new(doc),
+        }
+    }
+
+    pub fn is_human(&self) -> bool {
+        self.format == OutputFormat::Human
+    }
+
+    pub fn phase(&self, phase: Phase) {
+        *self.phase.borrow_mut() = phase;
+        match self.format {
+            OutputFormat::Human => match phase {
+                Phase::Detect => println!("Detect changes"),
+                Phase::Apply => println!("Apply changes"),
+            },
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Phase {
+                phase,
+                repository: self.repository.borrow().as_deref(),
+            }),
+        }
+    }
+
+    pub fn revisions(&self, old_revision: Option<&str>, new_revision: &str) {
+        let mut doc = self.doc.borrow_mut();
+        doc.old_revision = old_revision.map(String::from);
+        doc.new_revision = Some(String::from(new_revision));
+        if self.format == OutputFormat::Ndjson {
+            self.emit(&Event::Revisions {
+                old_revision,
+                new_revision,
+                repository: self.repository.borrow().as_deref(),
+            });
+        }
+    }
+
+    pub fn action(&self, mut action: Action) {
+        let phase = *self.phase.borrow();
+        action.repository = self.repository.borrow().clone();
+        match self.format {
+            OutputFormat::Human => {
+                if phase == Phase::Detect {
+                    println!("  {}: {}", action.indicator(), action.path);
+                }
+            }
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Action {
+                phase,
+                action: &action,
+            }),
+        }
+
+        let mut doc = self.doc.borrow_mut();
+        match phase {
+            Phase::Detect => doc.planned.push(action),
+            Phase::Apply => doc.applied.push(action),
+        }
+    }
+
+    pub fn hook(&self, command: &str) {
+        let hook = Hook {
+            command: String::from(command),
+            repository: self.repository.borrow().clone(),
+        };
+        match self.format {
+            OutputFormat::Human => println!("   hook  : {}", command),
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Hook(&hook)),
+        }
+        self.doc.borrow_mut().hooks.push(hook);
+    }
+
+    /// Records the file exported by `git skel export-patch`.
+    ///
+    /// The patch series may be written to stdout, so human readable output goes to stderr.
+    pub fn patch(&self, path: &str) {
+        match self.format {
+            OutputFormat::Human => eprintln!("   patch : {}", path),
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Patch { path }),
+        }
+        self.doc.borrow_mut().patches.push(String::from(path));
+    }
+
+    pub fn status(&self, status: Status) {
+        let describe = |revision: &str, tag: Option<&String>| {
+            if let Some(tag) = tag.or(status.tag.as_ref()) {
+                format!("{} ({})", revision, tag)
+            } else {
+                String::from(revision)
+            }
+        };
+
+        match self.format {
+            OutputFormat::Human => {
+                println!("url      : {}", status.url);
+                if let Some(ref branch) = status.branch {
+                    println!("branch   : {}", branch);
+                }
+                if let Some(ref tag) = status.tag {
+                    println!("tag      : {}", tag);
+                }
+                println!(
+                    "applied  : {}",
+                    describe(&status.applied_revision, status.applied_tag.as_ref())
+                );
+                println!(
+                    "latest   : {}",
+                    describe(&status.latest_revision, status.latest_tag.as_ref())
+                );
+                for parent in &status.parents {
+                    println!("parent   : {}", parent.url);
+                    println!(
+                        "  applied: {}",
+                        parent.applied_revision.as_deref().unwrap_or("-")
+                    );
+                    println!("  latest : {}", parent.latest_revision);
+                }
+                if status.up_to_date {
+                    println!("status   : up to date");
+                } else {
+                    println!("status   : update available");
+                }
+            }
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Status(&status)),
+        }
+        self.doc.borrow_mut().status = Some(status);
+    }
+
+    pub fn check(&self, check: Check) {
+        match self.format {
+            OutputFormat::Human => {
+                println!("applied  : {}", check.applied_revision);
+                println!("latest   : {}", check.latest_revision);
+                println!("drift    : {} files", check.drift.len());
+                println!("result   : {}", check.result.replace('-', " "));
+            }
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Check(&check)),
+        }
+        self.doc.borrow_mut().check = Some(check);
+    }
+
+    pub fn validation(&self, item: &'static str, result: Result<(), Error>) {
+        let validation = Validation {
+            item,
+            error: result.err().map(|x| {
+                let causes: Vec<_> = x.iter_chain().map(|x| format!("{}", x)).collect();
+                causes.join(": ")
+            }),
+        };
+        match self.format {
+            OutputFormat::Human => match validation.error {
+                Some(ref x) => println!("  !error : {}: {}", item, x),
+                None => println!("   ok    : {}", item),
+            },
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Validation(&validation)),
+        }
+        self.doc.borrow_mut().validations.push(validation);
+    }
+
+    /// Shows the value of `git skel config get`.
+    pub fn value(&self, key: &str, value: toml::Value) {
+        match self.format {
+            OutputFormat::Human => match value {
+                toml::Value::String(ref x) => println!("{}", x),
+                toml::Value::Table(ref x) => print!("{}", toml::to_string(x).unwrap_or_default()),
+                ref x => println!("{}", x),
+            },
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Value { key, value: &value }),
+        }
+        self.doc.borrow_mut().value = Some(value);
+    }
+
+    /// Shows the value of `git skel config`, and where it comes from if `origin` is given.
+    pub fn entry(&self, key: &str, value: toml::Value, origin: Option<&'static str>) {
+        let entry = Entry {
+            key: String::from(key),
+            value,
+            origin,
+        };
+        match self.format {
+            OutputFormat::Human => match origin {
+                Some(x) => println!("{:<20} {} = {}", x, entry.key, entry.value),
+                None => println!("{} = {}", entry.key, entry.value),
+            },
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Entry(&entry)),
+        }
+        self.doc.borrow_mut().entries.push(entry);
+    }
+
+    /// Returns the actions detected so far.
+    pub fn planned(&self) -> Vec<Action> {
+        self.doc.borrow().planned.clone()
+    }
+
+    /// Starts the actions of the repository in `git skel batch`.
+    pub fn repository(&self, path: &str) {
+        *self.repository.borrow_mut() = Some(String::from(path));
+        if self.is_human() {
+            println!("{}", console::style(path).white().bold());
+        }
+    }
+
+    /// Finishes the actions of the repository in `git skel batch`.
+    pub fn outcome(&self, path: &str, outcome: &Outcome) {
+        *self.repository.borrow_mut() = None;
+        let (result, error) = match outcome {
+            Outcome::UpToDate => ("up-to-date", None),
+            Outcome::Updated => ("updated", None),
+            Outcome::Conflicts => ("conflicts", None),
+            Outcome::Error(x) => ("error", Some(x.clone())),
+        };
+        let repository = Repository {
+            path: String::from(path),
+            result,
+            error,
+        };
+
+        match self.format {
+            OutputFormat::Human => {
+                if let Outcome::Error(ref x) = outcome {
+                    println!("  {} {}", console::style("Error:").red().bold(), x);
+                }
+            }
+            OutputFormat::Json => (),
+            OutputFormat::Ndjson => self.emit(&Event::Repository(&repository)),
+        }
+        self.doc.borrow_mut().repositories.push(repository);
+    }
+
+    /// Renders the summary. The error is printed to stderr by the caller in human readable format.
+    pub fn finish(&self, error: Option<&Error>) {
+        let mut doc = self.doc.borrow_mut();
+
+        let mut summary = Summary {
+            result: if error.is_some() { "error" } else { "ok" },
+            error: error.map(|x| {
+                let causes: Vec<_> = x.iter_chain().map(|x| format!("{}", x)).collect();
+                causes.join(": ")
+            }),
+            ..Default::default()
+        };
+        for x in &doc.planned {
+            *summary.planned.entry(kind_name(x.action)).or_insert(0) += 1;
+            if x.warn() {
+                summary.warnings += 1;
+            }
+        }
+        for x in doc.applied.iter().filter(|x| x.is_change()) {
+            *summary.applied.entry(kind_name(x.action)).or_insert(0) += 1;
+        }
+
+        match self.format {
+            OutputFormat::Human => {
+                if !doc.repositories.is_empty() {
+                    let width = doc.repositories.iter().map(|x| x.path.len()).max();
+                    let width = width.unwrap_or(0);
+                    println!();
+                    println!("Summary");
+                    for x in &doc.repositories {
+                        let result = match x.error {
+                            Some(ref e) => format!("error: {}", e.lines().next().unwrap_or("")),
+                            None => x.result.replace('-', " "),
+                        };
+                        println!("  {:width$} : {}", x.path, result, width = width);
+                    }
+                }
+            }
+            OutputFormat::Json => {
+                doc.summary = summary;
+                if let Ok(x) = serde_json::to_string_pretty(&*doc) {
+                    println!("{}", x);
+                }
+            }
+            OutputFormat::Ndjson => self.emit(&Event::Summary(&summary)),
+        }
+    }
+
+    fn emit(&self, event: &Event) {
+        if let Ok(x) = serde_json::to_string(event) {
+            println!("{}", x);
+        }
+    }
+}
+
+fn kind_name(kind: Kind) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|x| x.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn action() -> Action {
+        Action::new(
+            Path::new("src/main.rs"),
+            Kind::Copy,
+            Reason::Uncommitted,
+            None,
+            LocalStatus::Modified,
+            Strategy::Copy,
+        )
+    }
+
+    #[test]
+    fn test_document() {
+        let doc = Document {
+            command: "update",
+            old_revision: Some(String::from("a")),
+            new_revision: Some(String::from("b")),
+            planned: vec![action()],
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&doc).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "command": "update",
+                "old_revision": "a",
+                "new_revision": "b",
+                "planned": [{
+                    "path": "src/main.rs",
+                    "action": "copy",
+                    "reason": "uncommitted",
+                    "ignore": null,
+                    "status": "modified",
+                    "strategy": "copy",
+                }],
+                "applied": [],
+                "hooks": [],
+                "summary": {
+                    "result": "",
+                    "planned": {},
+                    "applied": {},
+                    "warnings": 0,
+                    "error": null,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_event() {
+        let mut action = action();
+        action.repository = Some(String::from("proj"));
+        let event = Event::Action {
+            phase: Phase::Apply,
+            action: &action,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "type": "action",
+                "phase": "apply",
+                "path": "src/main.rs",
+                "action": "copy",
+                "reason": "uncommitted",
+                "ignore": null,
+                "status": "modified",
+                "strategy": "copy",
+                "repository": "proj",
+            })
+        );
+
+        let event = Event::Revisions {
+            old_revision: None,
+            new_revision: "b",
+            repository: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({"type": "revisions", "old_revision": null, "new_revision": "b"})
+        );
+
+        let summary = Summary {
+            result: "ok",
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(Event::Summary(&summary)).unwrap(),
+            json!({
+                "type": "summary",
+                "result": "ok",
+                "planned": {},
+                "applied": {},
+                "warnings": 0,
+                "error": null,
+            })
+        );
+    }
+}
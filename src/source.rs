@@ -0,0 +1,419 @@
+use crate::archive;
+use crate::config::{Config, Layer};
+use crate::error::ErrorKind;
+use crate::manifest::{self, MANIFEST_FILE};
+use failure::{bail, Error, ResultExt};
+use git2::build::{CheckoutBuilder, TreeUpdateBuilder};
+use git2::{
+    Commit, FileMode, ObjectType, Oid, Repository, Signature, StatusOptions, Tree, TreeWalkMode,
+    TreeWalkResult,
+};
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Suffix of the revision recorded from uncommitted changes of a local skeleton
+pub const DIRTY_SUFFIX: &str = "-dirty";
+
+/// How the revision of a skeleton repository is recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// Commit id
+    Git,
+    /// Commit id of the base of uncommitted changes with `-dirty` suffix
+    Dirty,
+    /// Digest of the archive
+    Archive,
+}
+
+/// Skeleton repository checked out to a temporary directory
+///
+/// If the skeleton has parent skeletons, HEAD and the working tree are the composed tree of all layers.
+pub struct Source {
+    repo: Repository,
+    /// Revision of the skeleton repository itself
+    pub revision: String,
+    /// Whether the revision includes uncommitted changes
+    pub dirty: bool,
+    commit: Oid,
+    /// Revisions of the parent skeletons from the nearest one
+    pub layers: Vec<Layer>,
+    _dirs: Vec<TempDir>,
+}
+
+impl Deref for Source {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repo
+    }
+}
+
+impl Source {
+    pub fn new(repo: Repository, dir: TempDir, origin: Origin) -> Result<Source, Error> {
+        let (commit, revision) = revision_of(&repo, origin)?;
+        Ok(Source {
+            repo,
+            revision,
+            dirty: origin == Origin::Dirty,
+            commit,
+            layers: Vec::new(),
+            _dirs: vec![dir],
+        })
+    }
+
+    /// Adds the parent skeleton checked out at the revision to be applied.
+    ///
+    /// The objects of the parent are made visible to the skeleton repository to compose them.
+    pub fn add_layer(
+        &mut self,
+        url: &str,
+        parent: Repository,
+        dir: TempDir,
+        origin: Origin,
+    ) -> Result<(), Error> {
+        let (_, revision) = revision_of(&parent, origin)?;
+        let objects = parent.path().join("objects");
+        self.repo
+            .odb()?
+            .add_disk_alternate(&objects.to_string_lossy())?;
+        for reference in parent.references_glob("refs/archives/*")? {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                self.repo.reference(name, target, true, "git-skel: layer")?;
+            }
+        }
+
+        self.layers.push(Layer {
+            url: String::from(url),
+            revision,
+        });
+        self._dirs.push(dir);
+        Ok(())
+    }
+
+    /// Checks out the composed tree of all layers.
+    pub fn compose(&self) -> Result<(), Error> {
+        if self.layers.is_empty() {
+            return Ok(());
+        }
+        let commit = compose_commit(&self.repo, self.commit()?, &self.layers)?;
+        checkout(&self.repo, &commit)
+    }
+
+    /// Returns whether the revisions of all layers are applied.
+    pub fn is_applied(&self, config: &Config) -> bool {
+        self.revision == config.revision && self.layers == config.layers
+    }
+
+    /// Returns the revision of the skeleton repository itself.
+    pub fn commit(&self) -> Result<Commit<'_>, Error> {
+        Ok(self.repo.find_commit(self.commit)?)
+    }
+}
+
+/// Composes the skeleton revision and the parent revisions into a commit.
+///
+/// Files of a child layer override files of the parent layers, and skeleton manifests are merged.
+pub fn compose<'a>(
+    repo: &'a Repository,
+    revision: &str,
+    layers: &[Layer],
+) -> Result<Commit<'a>, Error> {
+    let child = find_revision(repo, revision)
+        .context(ErrorKind::RevisionNotFound(String::from(revision)))?;
+    compose_commit(repo, child, layers)
+}
+
+fn compose_commit<'a>(
+    repo: &'a Repository,
+    child: Commit<'a>,
+    layers: &[Layer],
+) -> Result<Commit<'a>, Error> {
+    if layers.is_empty() {
+        return Ok(child);
+    }
+
+    let mut tree: Option<Tree> = None;
+    for layer in layers.iter().rev() {
+        let commit = find_revision(repo, &layer.revision)
+            .context(ErrorKind::RevisionNotFound(layer.revision.clone()))?;
+        tree = Some(match tree {
+            Some(base) => overlay(repo, &base, &commit.tree()?)?,
+            None => commit.tree()?,
+        });
+    }
+    let tree = match tree {
+        Some(base) => overlay(repo, &base, &child.tree()?)?,
+        None => return Ok(child),
+    };
+
+    let sig = Signature::now("git-skel", "git-skel")?;
+    let id = repo.commit(
+        None,
+        &sig,
+        &sig,
+        "Compose skeleton layers",
+        &tree,
+        &[&child],
+    )?;
+    Ok(repo.find_commit(id)?)
+}
+
+fn overlay<'a>(repo: &'a Repository, base: &Tree, layer: &Tree) -> Result<Tree<'a>, Error> {
+    let manifest = |tree: &Tree| -> Result<Option<String>, Error> {
+        match tree.get_name(MANIFEST_FILE) {
+            Some(entry) => {
+                let blob = repo.find_blob(entry.id())?;
+                Ok(Some(String::from_utf8(blob.content().to_vec())?))
+            }
+            None => Ok(None),
+        }
+    };
+    let merged = match (manifest(base)?, manifest(layer)?) {
+        (Some(parent), Some(child)) => Some(manifest::merge(&child, &parent)?),
+        _ => None,
+    };
+
+    let mut builder = TreeUpdateBuilder::new();
+    if let Some(ref merged) = merged {
+        builder.upsert(MANIFEST_FILE, repo.blob(merged.as_bytes())?, FileMode::Blob);
+    }
+    layer.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let path = format!("{}{}", root, entry.name().unwrap_or_default());
+            if merged.is_some() && path == MANIFEST_FILE {
+                return TreeWalkResult::Ok;
+            }
+            let mode = match entry.filemode() {
+                0o100_755 => FileMode::BlobExecutable,
+                0o120_000 => FileMode::Link,
+                _ => FileMode::Blob,
+            };
+            builder.upsert(path, entry.id(), mode);
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    Ok(repo.find_tree(builder.create_updated(repo, base)?)?)
+}
+
+pub fn checkout(repo: &Repository, commit: &Commit) -> Result<(), Error> {
+    let mut builder = CheckoutBuilder::new();
+    builder.force();
+    repo.checkout_tree(commit.as_object(), Some(&mut builder))?;
+    repo.set_head_detached(commit.id())?;
+    Ok(())
+}
+
+fn revision_of(repo: &Repository, origin: Origin) -> Result<(Oid, String), Error> {
+    let commit = repo.head()?.peel_to_commit()?;
+    let revision = match origin {
+        Origin::Git => format!("{}", commit.id()),
+        Origin::Dirty => format!("{}{}", commit.parent_id(0)?, DIRTY_SUFFIX),
+        Origin::Archive => {
+            let mut revision = format!("{}", commit.id());
+            for reference in repo.references_glob("refs/archives/*")? {
+                let reference = reference?;
+                if reference.target() == Some(commit.id()) {
+                    let digest = reference.name().unwrap_or_default();
+                    let digest = digest.trim_start_matches("refs/archives/");
+                    revision = format!("{}{}", archive::REVISION_PREFIX, digest);
+                }
+            }
+            revision
+        }
+    };
+    Ok((commit.id(), revision))
+}
+
+/// Returns the commit id of the revision.
+///
+/// Uncommitted changes of a dirty revision are not recorded, so the base commit of them is used.
+fn revision_id(revision: &str) -> &str {
+    revision.trim_end_matches(DIRTY_SUFFIX)
+}
+
+/// Finds the commit of the revision recorded in `.gitskel.toml`.
+pub fn find_revision<'a>(repo: &'a Repository, revision: &str) -> Result<Commit<'a>, Error> {
+    if let Some(digest) = archive::digest(revision) {
+        let reference = repo.find_reference(&format!("refs/archives/{}", digest))?;
+        return Ok(reference.peel_to_commit()?);
+    }
+    Ok(repo.find_commit(Oid::from_str(revision_id(revision))?)?)
+}
+
+/// Returns the directory to cache skeletons.
+///
+/// `GIT_SKEL_CACHE_DIR` overrides the default directory ( `~/.cache/git-skel` on Linux ).
+pub fn cache_dir() -> Result<PathBuf, Error> {
+    let dir = match env::var_os("GIT_SKEL_CACHE_DIR") {
+        Some(x) => PathBuf::from(x),
+        None => match dirs::cache_dir() {
+            Some(x) => x.join("git-skel"),
+            None => bail!(ErrorKind::CacheNotFound),
+        },
+    };
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Returns the path if `url` is a local directory or a `file://` URL.
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    let path = Path::new(url.trim_start_matches("file://"));
+    if path.is_dir() {
+        fs::canonicalize(path).ok()
+    } else {
+        None
+    }
+}
+
+/// Returns the absolute path if `url` is a local directory or file, otherwise `url` as is.
+///
+/// Relative paths are resolved against the working directory, so they are recorded as absolute paths.
+pub fn canonical_url(url: &str) -> String {
+    let path = Path::new(url.trim_start_matches("file://"));
+    match fs::canonicalize(path) {
+        Ok(x) if url.starts_with("file://") || !url.contains("://") => {
+            x.to_string_lossy().to_string()
+        }
+        _ => String::from(url),
+    }
+}
+
+/// Opens a local skeleton repository without cloning.
+///
+/// The local repository is read through git alternates, so that its working tree is never touched.
+/// If `dirty` is true, uncommitted changes of the working tree are committed into the returned repository,
+/// and whether there are the changes is returned.
+pub fn open_local(path: &Path, dir: &Path, dirty: bool) -> Result<(Repository, bool), Error> {
+    let local = Repository::open(path)?;
+
+    let mut refs = Vec::new();
+    for reference in local.references()? {
+        let reference = reference?;
+        if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+            refs.push((String::from(name), target));
+        }
+    }
+    let head = local.head()?.peel_to_commit()?.id();
+    let repo = open_shared(&local.path().join("objects"), dir, &refs, head)?;
+
+    let dirty = dirty && commit_dirty(&local, &repo, head)?;
+    Ok((repo, dirty))
+}
+
+/// Creates a repository which reads objects from `objects` through git alternates.
+///
+/// Branches of `refs` are mapped as the same as remote-tracking branches of a clone.
+pub fn open_shared(
+    objects: &Path,
+    dir: &Path,
+    refs: &[(String, Oid)],
+    head: Oid,
+) -> Result<Repository, Error> {
+    Repository::init(dir)?;
+    let repo = Repository::open(dir)?;
+    fs::write(
+        repo.path().join("objects").join("info").join("alternates"),
+        format!("{}\n", objects.to_string_lossy()),
+    )?;
+    let repo = Repository::open(dir)?;
+
+    for (name, target) in refs {
+        let name = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            format!("refs/remotes/origin/{}", branch)
+        } else if name.starts_with("refs/tags/") || name.starts_with("refs/archives/") {
+            name.clone()
+        } else {
+            continue;
+        };
+        repo.reference(&name, *target, true, "git-skel: shared")?;
+    }
+    repo.set_head_detached(head)?;
+
+    Ok(repo)
+}
+
+/// Opens a repository sharing the objects and the branches of the fetched skeleton repository.
+///
+/// The fetched repository is kept as is, so that other revisions can be checked out from it later.
+pub fn open_fetched(fetched: &Repository, dir: &Path) -> Result<Repository, Error> {
+    let mut refs = Vec::new();
+    for reference in fetched.references()? {
+        let reference = reference?;
+        if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+            // Remote-tracking branches are mapped to branches, and mapped again by `open_shared`
+            let name = match name.strip_prefix("refs/remotes/origin/") {
+                Some(x) => format!("refs/heads/{}", x),
+                None => String::from(name),
+            };
+            refs.push((name, target));
+        }
+    }
+    let head = fetched.head()?.peel_to_commit()?.id();
+    open_shared(&fetched.path().join("objects"), dir, &refs, head)
+}
+
+fn commit_dirty(local: &Repository, repo: &Repository, head: Oid) -> Result<bool, Error> {
+    let workdir = match local.workdir() {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let head = repo.find_commit(head)?;
+    let head_tree = head.tree()?;
+
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+
+    let mut builder = TreeUpdateBuilder::new();
+    let mut changed = false;
+    for entry in local.statuses(Some(&mut opts))?.iter() {
+        let path = match entry.path() {
+            Some(x) => x,
+            None => continue,
+        };
+        let file = workdir.join(path);
+        match fs::symlink_metadata(&file) {
+            Ok(x) if x.file_type().is_symlink() => {
+                let target = fs::read_link(&file)?;
+                let blob = repo.blob(target.to_string_lossy().as_bytes())?;
+                builder.upsert(path, blob, FileMode::Link);
+            }
+            Ok(x) if x.is_file() => {
+                let blob = repo.blob(&fs::read(&file)?)?;
+                let mode = match head_tree.get_path(Path::new(path)) {
+                    Ok(x) if x.filemode() == 0o100_755 => FileMode::BlobExecutable,
+                    _ => FileMode::Blob,
+                };
+                builder.upsert(path, blob, mode);
+            }
+            _ => {
+                builder.remove(path);
+            }
+        }
+        changed = true;
+    }
+
+    if changed {
+        let tree = repo.find_tree(builder.create_updated(repo, &head_tree)?)?;
+        let sig = Signature::now("git-skel", "git-skel")?;
+        let id = repo.commit(None, &sig, &sig, "Uncommitted changes", &tree, &[&head])?;
+        repo.set_head_detached(id)?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revision_id() {
+        let id = "f5ca092fecc42c260131f5c42a64f67d4cf58561";
+        assert_eq!(revision_id(id), id);
+        assert_eq!(revision_id(&format!("{}{}", id, DIRTY_SUFFIX)), id);
+    }
+}
@@ -0,0 +1,100 @@
+use failure::Error;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Copies and deletes files, and aborts if the files are modified locally
+    Copy,
+    /// Copies and deletes files even if the files are modified locally
+    Overwrite,
+    /// Copies files only if the files don't exist, and never deletes them
+    CreateOnce,
+    /// Merges the skeleton changes into the local files by three-way merge
+    Merge,
+    /// Copies files as the same as `copy`, but never deletes them
+    NeverDelete,
+    /// Merges TOML, JSON and YAML files at key level, and falls back to `merge` for the other files
+    Structured,
+    /// Replaces only the managed block between `>>> git-skel` and `<<< git-skel` markers
+    Block,
+}
+
+/// Rule to select the strategy of the files matched with `path`
+///
+/// `path` is a glob relative to the repository root. `*` doesn't match `/`, and `**` matches any directories.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    pub path: String,
+    pub strategy: Strategy,
+}
+
+pub struct Rules {
+    rules: Vec<(GlobMatcher, Strategy)>,
+}
+
+impl Rules {
+    /// Creates rules from the rules of project and skeleton.
+    ///
+    /// The rules of project take precedence over the rules of skeleton,
+    /// and the first matched rule is used in each of them.
+    pub fn new(project: &[Rule], skeleton: &[Rule]) -> Result<Rules, Error> {
+        let mut rules = Vec::new();
+        for rule in project.iter().chain(skeleton.iter()) {
+            let glob = GlobBuilder::new(&rule.path)
+                .literal_separator(true)
+                .build()?;
+            rules.push((glob.compile_matcher(), rule.strategy));
+        }
+        Ok(Rules { rules })
+    }
+
+    pub fn get(&self, path: &Path) -> Strategy {
+        self.rules
+            .iter()
+            .find(|(glob, _)| glob.is_match(path))
+            .map(|(_, strategy)| *strategy)
+            .unwrap_or(Strategy::Copy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let project = vec![Rule {
+            path: String::from("LICENSE"),
+            strategy: Strategy::CreateOnce,
+        }];
+        let skeleton: Vec<Rule> = toml::from_str::<toml::Table>(
+            r#"
+            rules = [
+                { path = "LICENSE", strategy = "overwrite" },
+                { path = "src/main.rs", strategy = "create-once" },
+                { path = ".github/workflows/*", strategy = "merge" },
+            ]
+            "#,
+        )
+        .unwrap()["rules"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let rules = Rules::new(&project, &skeleton).unwrap();
+        assert_eq!(rules.get(Path::new("LICENSE")), Strategy::CreateOnce);
+        assert_eq!(rules.get(Path::new("src/main.rs")), Strategy::CreateOnce);
+        assert_eq!(
+            rules.get(Path::new(".github/workflows/ci.yml")),
+            Strategy::Merge
+        );
+        assert_eq!(
+            rules.get(Path::new(".github/workflows/x/ci.yml")),
+            Strategy::Copy
+        );
+        assert_eq!(rules.get(Path::new("README.md")), Strategy::Copy);
+    }
+}
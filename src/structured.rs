@@ -0,0 +1,230 @@
+use crate::config;
+use failure::{bail, format_err, Error};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Some(Format::Toml),
+            Some("json") => Some(Format::Json),
+            Some("yml") | Some("yaml") => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    fn parse(self, text: &[u8]) -> Result<Value, Error> {
+        let text = std::str::from_utf8(text)?;
+        let value = match self {
+            Format::Toml => serde_json::to_value(toml::from_str::<toml::Value>(text)?)?,
+            Format::Json => serde_json::from_str(text)?,
+            Format::Yaml => serde_json::to_value(serde_yaml::from_str::<serde_yaml::Value>(text)?)?,
+        };
+        Ok(value)
+    }
+
+    /// Writes the value over the original text.
+    ///
+    /// TOML is edited in place, and JSON and YAML are re-serialized.
+    fn write(self, text: &[u8], value: &Value) -> Result<Vec<u8>, Error> {
+        let text = match self {
+            Format::Toml => {
+                if !value.is_object() {
+                    bail!("the top level of TOML must be a table");
+                }
+                config::edit(std::str::from_utf8(text)?, &toml::to_string(value)?)?
+            }
+            Format::Json => {
+                let mut x = serde_json::to_string_pretty(value)?;
+                x.push('\n');
+                x
+            }
+            Format::Yaml => serde_yaml::to_string(value)?,
+        };
+        Ok(text.into_bytes())
+    }
+}
+
+/// Merges skeleton changes into the target file at key level.
+///
+/// - keys updated by skeleton are updated
+/// - keys only in project are kept
+/// - keys removed by skeleton are deleted only if project never changed them
+/// - values changed by both are conflicts, and the values of skeleton are taken
+///
+/// Only the changed values of TOML are rewritten, so comments and formatting of the target file are kept.
+/// JSON and YAML are re-serialized, so comments, key order and formatting are not kept.
+///
+/// Returns the merged content and whether conflicts exist, or `None` if the merged content is semantically the same as the target file.
+pub fn merge(
+    format: Format,
+    base: Option<&[u8]>,
+    ours: &[u8],
+    theirs: &[u8],
+) -> Result<Option<(Vec<u8>, bool)>, Error> {
+    let base = match base {
+        Some(x) => Some(format.parse(x)?),
+        None => None,
+    };
+    let ours_text = ours;
+    let ours = format.parse(ours)?;
+    let theirs = format.parse(theirs)?;
+
+    let mut conflict = false;
+    let merged = merge_value(base.as_ref(), &ours, &theirs, &mut conflict);
+    if merged == ours {
+        Ok(None)
+    } else {
+        let ret = format
+            .write(ours_text, &merged)
+            .map_err(|x| format_err!("failed to write merged value: {}", x))?;
+        Ok(Some((ret, conflict)))
+    }
+}
+
+fn merge_value(base: Option<&Value>, ours: &Value, theirs: &Value, conflict: &mut bool) -> Value {
+    match (ours, theirs) {
+        (Value::Object(ours), Value::Object(theirs)) => {
+            let base = base.and_then(Value::as_object);
+            let mut ret = Map::new();
+
+            for (key, our) in ours {
+                let base = base.and_then(|x| x.get(key));
+                match theirs.get(key) {
+                    Some(their) => {
+                        ret.insert(key.clone(), merge_value(base, our, their, conflict));
+                    }
+                    None => {
+                        // removed by skeleton
+                        if base != Some(our) {
+                            ret.insert(key.clone(), our.clone());
+                        }
+                    }
+                }
+            }
+
+            for (key, their) in theirs {
+                if ours.contains_key(key) {
+                    continue;
+                }
+                // removed by project
+                let base = base.and_then(|x| x.get(key));
+                if base != Some(their) {
+                    ret.insert(key.clone(), their.clone());
+                }
+            }
+
+            Value::Object(ret)
+        }
+        _ => {
+            if base == Some(theirs) || ours == theirs {
+                ours.clone()
+            } else {
+                // changed by both
+                if base != Some(ours) {
+                    *conflict = true;
+                }
+                theirs.clone()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let base = br#"
+            [package]
+            name = "skel"
+            edition = "2018"
+            readme = "README.md"
+
+            [dependencies]
+            serde = "1.0"
+        "#;
+        let ours = br#"
+            [package]
+            name = "project"
+            edition = "2018"
+
+            [dependencies]
+            serde = "1.0"
+            regex = "1.0"
+        "#;
+        let theirs = br#"
+            [package]
+            name = "skel"
+            edition = "2021"
+            license = "MIT"
+
+            [dependencies]
+            serde = "1.0"
+        "#;
+
+        let (merged, conflict) = merge(Format::Toml, Some(base), ours, theirs)
+            .unwrap()
+            .unwrap();
+        assert!(!conflict);
+        let merged = Format::Toml.parse(&merged).unwrap();
+        let expected = Format::Toml
+            .parse(
+                br#"
+                [package]
+                name = "project"
+                edition = "2021"
+                license = "MIT"
+
+                [dependencies]
+                serde = "1.0"
+                regex = "1.0"
+                "#,
+            )
+            .unwrap();
+        assert_eq!(merged, expected);
+
+        // Comments and formatting of TOML are kept
+        let (merged, _) = merge(
+            Format::Toml,
+            Some(b"[package]\nname = \"skel\"\nedition = \"2018\"\n"),
+            b"# my project\n[package]\nname    = \"project\" # keep\nedition = \"2018\"\n",
+            b"[package]\nname = \"skel\"\nedition = \"2021\"\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "# my project\n[package]\nname    = \"project\" # keep\nedition = \"2021\"\n"
+        );
+
+        let json = br#"{"a": 1, "b": [1, 2], "c": {"d": true}}"#;
+        assert!(merge(Format::Json, Some(json), json, json)
+            .unwrap()
+            .is_none());
+
+        let (merged, conflict) = merge(Format::Yaml, None, b"a: 1\nb: 2\n", b"a: 3\nc: 4\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(String::from_utf8(merged).unwrap(), "a: 3\nb: 2\nc: 4\n");
+        assert!(conflict);
+
+        let (_, conflict) = merge(
+            Format::Json,
+            Some(br#"{"a": 1}"#),
+            br#"{"a": 2}"#,
+            br#"{"a": 1, "b": 2}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(!conflict);
+    }
+}
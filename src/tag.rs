@@ -0,0 +1,94 @@
+use crate::error::ErrorKind;
+use failure::{bail, Error};
+use git2::Repository;
+use globset::Glob;
+use semver::{Version, VersionReq};
+
+enum Pattern {
+    Exact(String),
+    Glob(globset::GlobMatcher),
+    Semver(VersionReq),
+}
+
+impl Pattern {
+    fn parse(tag: &str) -> Result<Pattern, Error> {
+        let pattern = if tag.starts_with(|c| "^~=<>".contains(c)) {
+            Pattern::Semver(VersionReq::parse(tag)?)
+        } else if tag.contains(|c| "*?[{".contains(c)) {
+            Pattern::Glob(Glob::new(tag)?.compile_matcher())
+        } else {
+            Pattern::Exact(String::from(tag))
+        };
+        Ok(pattern)
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Pattern::Exact(x) => x == name,
+            Pattern::Glob(x) => x.is_match(name),
+            Pattern::Semver(x) => version(name).map(|v| x.matches(&v)).unwrap_or(false),
+        }
+    }
+}
+
+/// Returns true if `tag` is a glob or a semver requirement rather than an exact tag name.
+pub fn is_pattern(tag: &str) -> bool {
+    !matches!(Pattern::parse(tag), Ok(Pattern::Exact(_)))
+}
+
+/// Returns the highest tag of `repo` matching `tag`.
+///
+/// Tags which can be parsed as semver ( with or without `v` prefix ) are ordered by version,
+/// and the others are ordered by name.
+pub fn find(repo: &Repository, tag: &str) -> Result<String, Error> {
+    let pattern = Pattern::parse(tag)?;
+
+    let mut tags: Vec<_> = repo
+        .tag_names(None)?
+        .iter()
+        .flatten()
+        .filter(|x| pattern.is_match(x))
+        .map(String::from)
+        .collect();
+    tags.sort_by_key(|x| (version(x), x.clone()));
+
+    match tags.pop() {
+        Some(x) => Ok(x),
+        None => bail!(ErrorKind::TagNotFound(String::from(tag))),
+    }
+}
+
+/// Returns the resolved tag name to be recorded, if `tag` is a pattern.
+pub fn applied<T: AsRef<str>>(repo: &Repository, tag: Option<T>) -> Result<Option<String>, Error> {
+    match tag {
+        Some(ref x) if is_pattern(x.as_ref()) => Ok(Some(find(repo, x.as_ref())?)),
+        _ => Ok(None),
+    }
+}
+
+fn version(name: &str) -> Option<Version> {
+    Version::parse(name.trim_start_matches('v')).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        assert!(!is_pattern("v1.2.0"));
+        assert!(is_pattern("v1.*"));
+        assert!(is_pattern("^1.2"));
+
+        let glob = Pattern::parse("v1.*").unwrap();
+        assert!(glob.is_match("v1.2.0"));
+        assert!(!glob.is_match("v2.0.0"));
+
+        let semver = Pattern::parse("^1.2").unwrap();
+        assert!(semver.is_match("v1.3.0"));
+        assert!(semver.is_match("1.2.5"));
+        assert!(!semver.is_match("v1.1.0"));
+        assert!(!semver.is_match("v2.0.0"));
+        assert!(!semver.is_match("t1"));
+    }
+}
@@ -0,0 +1,114 @@
+use failure::{bail, Error};
+use git2::Repository;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Files rendered with variables, selected by `templates` globs of skeleton manifest
+pub struct Templates {
+    globs: GlobSet,
+}
+
+impl Templates {
+    pub fn new(globs: &[String]) -> Result<Templates, Error> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+        }
+        Ok(Templates {
+            globs: builder.build()?,
+        })
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.globs.is_match(path)
+    }
+
+    /// Renders the template files in the working tree of skeleton repository.
+    ///
+    /// Returns the files whose rendered content is different from the content rendered by `old_vars`.
+    pub fn render_tree(
+        &self,
+        src: &Repository,
+        vars: &Table,
+        old_vars: &Table,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let src_root = PathBuf::from(src.workdir().unwrap());
+
+        let mut ret = Vec::new();
+        for index in src.index()?.iter() {
+            let path = PathBuf::from(&String::from_utf8(index.path)?);
+            let src_path = src_root.join(&path);
+            if !self.is_match(&path) || fs::symlink_metadata(&src_path)?.file_type().is_symlink() {
+                continue;
+            }
+
+            // binary files are not rendered
+            let text = match String::from_utf8(fs::read(&src_path)?) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+
+            let rendered = render(&text, vars)
+                .map_err(|x| failure::format_err!("{}: {}", path.to_string_lossy(), x))?;
+            if render(&text, old_vars).ok().as_ref() != Some(&rendered) {
+                ret.push(path);
+            }
+            fs::write(&src_path, rendered)?;
+        }
+        Ok(ret)
+    }
+}
+
+/// Replaces `{{ name }}` by the value of the variable.
+pub fn render(text: &str, vars: &Table) -> Result<String, Error> {
+    let re = Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap();
+
+    let mut unknown = None;
+    let ret = re.replace_all(text, |caps: &Captures| match vars.get(&caps[1]) {
+        Some(Value::String(x)) => x.clone(),
+        Some(x) => format!("{}", x),
+        None => {
+            unknown.get_or_insert_with(|| String::from(&caps[1]));
+            String::new()
+        }
+    });
+
+    if let Some(unknown) = unknown {
+        bail!("unknown variable: {}", unknown);
+    }
+    Ok(ret.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut vars = Table::new();
+        vars.insert(String::from("name"), Value::from("skel"));
+        vars.insert(String::from("jobs"), Value::from(4));
+        vars.insert(String::from("docker"), Value::from(false));
+
+        assert_eq!(
+            render(
+                "name = \"{{ name }}\"\njobs = {{jobs}}\n{{ docker }}",
+                &vars
+            )
+            .unwrap(),
+            "name = \"skel\"\njobs = 4\nfalse"
+        );
+        assert_eq!(
+            render("${{ github.ref }}", &vars).unwrap(),
+            "${{ github.ref }}"
+        );
+        assert!(render("{{ unknown }}", &vars).is_err());
+
+        let templates = Templates::new(&[String::from("*.toml")]).unwrap();
+        assert!(templates.is_match(Path::new("Cargo.toml")));
+        assert!(!templates.is_match(Path::new("src/Cargo.toml")));
+    }
+}
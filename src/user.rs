@@ -0,0 +1,131 @@
+use crate::error::ErrorKind;
+use failure::{Error, ResultExt};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Shorthand prefixes available without user config
+const SHORTHANDS: &[(&str, &str)] = &[
+    ("gh", "https://github.com/{}.git"),
+    ("gl", "https://gitlab.com/{}.git"),
+];
+
+/// User config put at `~/.config/git-skel/config.toml`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UserConfig {
+    /// Branch tracked by `init` if it exists, and neither `--branch` nor `--tag` is given
+    pub branch: Option<String>,
+    /// Ignore patterns of skeleton files applied to all projects
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Names which can be used as URL of skeleton
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// URL templates of shorthand prefixes like `gh:org/repo` ( `{}` is replaced by `org/repo` )
+    #[serde(default)]
+    pub shorthands: BTreeMap<String, String>,
+}
+
+impl UserConfig {
+    /// Loads the user config.
+    ///
+    /// `GIT_SKEL_CONFIG` overrides the default path, and the default config is returned if the file doesn't exist.
+    pub fn load() -> Result<UserConfig, Error> {
+        let path = match env::var_os("GIT_SKEL_CONFIG") {
+            Some(x) => PathBuf::from(x),
+            None => match dirs::config_dir() {
+                Some(x) => x.join("git-skel").join("config.toml"),
+                None => return Ok(UserConfig::default()),
+            },
+        };
+        if !path.exists() {
+            return Ok(UserConfig::default());
+        }
+
+        fn load_file(path: &Path) -> Result<UserConfig, Error> {
+            let s = fs::read_to_string(path)?;
+            let config = toml::from_str(&s)?;
+            Ok(config)
+        }
+
+        let config = load_file(&path).context(ErrorKind::UserConfigLoad(
+            path.to_string_lossy().to_string(),
+        ))?;
+        Ok(config)
+    }
+
+    /// Expands the alias or the shorthand to the canonical URL.
+    pub fn expand(&self, url: &str) -> String {
+        let url = self.aliases.get(url).map(String::as_str).unwrap_or(url);
+
+        if let Some((prefix, rest)) = url.split_once(':') {
+            let template = self.shorthands.get(prefix).map(String::as_str).or_else(|| {
+                SHORTHANDS
+                    .iter()
+                    .find(|(x, _)| *x == prefix)
+                    .map(|(_, x)| *x)
+            });
+            if let Some(template) = template {
+                let rest = if template.contains("{}.git") {
+                    rest.trim_end_matches(".git")
+                } else {
+                    rest
+                };
+                return if template.contains("{}") {
+                    template.replace("{}", rest)
+                } else {
+                    format!("{}{}", template, rest)
+                };
+            }
+        }
+
+        String::from(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let config: UserConfig = toml::from_str(
+            r#"
+            [aliases]
+            rust-cli = "https://git.example/templates/rust-cli.git"
+            org = "gh:example/org-skel"
+
+            [shorthands]
+            ex = "https://git.example/"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.expand("rust-cli"),
+            "https://git.example/templates/rust-cli.git"
+        );
+        assert_eq!(
+            config.expand("org"),
+            "https://github.com/example/org-skel.git"
+        );
+        assert_eq!(
+            config.expand("gh:dalance/git-skel.git"),
+            "https://github.com/dalance/git-skel.git"
+        );
+        assert_eq!(
+            config.expand("ex:templates/go.git"),
+            "https://git.example/templates/go.git"
+        );
+        assert_eq!(
+            config.expand("git@github.com:dalance/git-skel.git"),
+            "git@github.com:dalance/git-skel.git"
+        );
+        assert_eq!(
+            config.expand("https://github.com/dalance/git-skel.git"),
+            "https://github.com/dalance/git-skel.git"
+        );
+    }
+}
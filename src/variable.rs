@@ -0,0 +1,219 @@
+use crate::error::ErrorKind;
+use console::Term;
+use failure::{bail, format_err, Error, ResultExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VarType {
+    #[default]
+    String,
+    Bool,
+    Integer,
+}
+
+/// Variable declared by `[[variable]]` of skeleton manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, rename = "type")]
+    pub kind: VarType,
+    pub default: Option<Value>,
+    /// Regular expression which string values must match
+    pub validation: Option<String>,
+}
+
+impl Variable {
+    /// Checks the declaration itself.
+    pub fn validate(&self) -> Result<(), Error> {
+        let re = Regex::new("^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+        if !re.is_match(&self.name) {
+            bail!("invalid variable name: {}", self.name);
+        }
+        if let Some(ref validation) = self.validation {
+            if self.kind != VarType::String {
+                bail!(
+                    "validation is available for string variable only: {}",
+                    self.name
+                );
+            }
+            Regex::new(validation)
+                .map_err(|x| format_err!("invalid validation of {}: {}", self.name, x))?;
+        }
+        if let Some(ref default) = self.default {
+            self.check(default)
+                .map_err(|x| format_err!("invalid default of {}: {}", self.name, x))?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether the value has the declared type and matches the validation.
+    pub fn check(&self, value: &Value) -> Result<(), Error> {
+        match (self.kind, value) {
+            (VarType::String, Value::String(x)) => {
+                if let Some(ref validation) = self.validation {
+                    let re = Regex::new(validation)?;
+                    if !re.is_match(x) {
+                        bail!("'{}' doesn't match '{}'", x, validation);
+                    }
+                }
+                Ok(())
+            }
+            (VarType::Bool, Value::Boolean(_)) => Ok(()),
+            (VarType::Integer, Value::Integer(_)) => Ok(()),
+            (kind, value) => bail!("{} is not {:?}", value, kind),
+        }
+    }
+
+    /// Converts the value given as a string ( `--var` or prompt ) to the declared type, and checks it.
+    pub fn coerce(&self, value: &Value) -> Result<Value, Error> {
+        let value = match (self.kind, value) {
+            (VarType::Bool, Value::String(x)) => match x.to_lowercase().as_str() {
+                "true" | "yes" | "y" => Value::Boolean(true),
+                "false" | "no" | "n" => Value::Boolean(false),
+                _ => bail!("'{}' is not bool", x),
+            },
+            (VarType::Integer, Value::String(x)) => Value::Integer(
+                x.parse()
+                    .map_err(|_| format_err!("'{}' is not integer", x))?,
+            ),
+            (_, x) => x.clone(),
+        };
+        self.check(&value)?;
+        Ok(value)
+    }
+
+    fn prompt(&self, term: &Term) -> Result<Value, Error> {
+        let description = if self.description.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ( {} )", self.description, self.name)
+        };
+        let default = match self.default {
+            Some(Value::String(ref x)) => format!(" [{}]", x),
+            Some(ref x) => format!(" [{}]", x),
+            None => String::new(),
+        };
+
+        loop {
+            term.write_str(&format!("{}{}: ", description, default))?;
+            let answer = term.read_line()?;
+            let answer = answer.trim();
+
+            if answer.is_empty() {
+                if let Some(ref x) = self.default {
+                    return Ok(x.clone());
+                }
+                continue;
+            }
+
+            match self.coerce(&Value::from(answer)) {
+                Ok(x) => return Ok(x),
+                Err(x) => term.write_line(&format!(
+                    "  {} {}",
+                    console::style("Error:").red().bold(),
+                    x
+                ))?,
+            }
+        }
+    }
+}
+
+/// Resolves the values of the declared variables into `vars`.
+///
+/// Each value is taken from `given` ( `--var` and `--vars-file` ), `vars` ( answers recorded in `.gitskel.toml` ),
+/// the prompt on terminal or the default in order.
+/// If `prompt` is false, the variable without any value is an error even on terminal.
+pub fn resolve(
+    variables: &[Variable],
+    vars: &mut Table,
+    given: &Table,
+    prompt: bool,
+) -> Result<(), Error> {
+    for name in given.keys() {
+        if !variables.iter().any(|x| &x.name == name) {
+            bail!(ErrorKind::VariableUnknown(name.clone()));
+        }
+    }
+
+    let term = Term::stderr();
+    for var in variables {
+        let value = if let Some(x) = given.get(&var.name) {
+            var.coerce(x)
+                .context(ErrorKind::VariableInvalid(var.name.clone()))?
+        } else if vars.contains_key(&var.name) {
+            continue;
+        } else if prompt && term.is_term() {
+            var.prompt(&term)?
+        } else if let Some(ref x) = var.default {
+            x.clone()
+        } else {
+            bail!(ErrorKind::VariableRequired(var.name.clone()));
+        };
+        vars.insert(var.name.clone(), value);
+    }
+
+    Ok(())
+}
+
+/// Parses `key=value` of `--var` option.
+pub fn parse_assignment(s: &str) -> Result<(String, Value), Error> {
+    match s.find('=') {
+        Some(i) => Ok((String::from(&s[..i]), Value::from(&s[i + 1..]))),
+        None => bail!("variable must be `key=value`: {}", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variable() {
+        let var: Variable = toml::from_str(
+            r#"
+            name = "crate_name"
+            default = "skel"
+            validation = "^[a-z_]+$"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(var.kind, VarType::String);
+        assert!(var.validate().is_ok());
+        assert!(var.check(&Value::from("my_crate")).is_ok());
+        assert!(var.check(&Value::from("MyCrate")).is_err());
+        assert!(var.check(&Value::from(true)).is_err());
+        assert!(var.coerce(&Value::from("my_crate")).is_ok());
+
+        let var: Variable = toml::from_str(
+            r#"
+            name = "use-docker"
+            type = "bool"
+            "#,
+        )
+        .unwrap();
+        assert!(var.validate().is_err());
+
+        let var: Variable = toml::from_str(
+            r#"
+            name = "jobs"
+            type = "integer"
+            default = "4"
+            "#,
+        )
+        .unwrap();
+        assert!(var.validate().is_err());
+        assert_eq!(var.coerce(&Value::from("8")).unwrap(), Value::from(8));
+        assert!(var.coerce(&Value::from("eight")).is_err());
+
+        assert_eq!(
+            parse_assignment("a=b=c").unwrap(),
+            (String::from("a"), Value::from("b=c"))
+        );
+        assert!(parse_assignment("a").is_err());
+    }
+}